@@ -1,38 +1,854 @@
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::path::Path;
-
-use crate::Months;
-
-pub fn read_archive<P: AsRef<Path>>(archive_path: P) -> Result<Months, String> {
-    return match File::open(archive_path) {
-        Ok(archive_file) => {
-            match serde_json::from_reader(archive_file) {
-                Ok(archive_months) => Ok(archive_months),
-                Err(error) => {
-                    Err(format!("Failed to parse archive: {:?}", error))
-                }
-            }
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Europe::Berlin;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::model::{assign_uids, Event, Months, Snapshot, sort_events};
+use crate::pipeline::{EventSink, EventSource};
+use crate::util::{sibling_path, write_atomically, Error, LockFile};
+
+/// The version of the archive format written by this version of the tool
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Migrations of the archive format, the first one migrates from version 1 to 2 and so on.
+/// Version 1 archives are bare months without any envelope.
+const MIGRATIONS: [fn(Value) -> serde_json::Result<Value>; 1] = [migrate_legacy_uids];
+
+/// Information about an archive that is stored next to its months
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ArchiveInfo {
+    /// The version of the archive format
+    pub format: u32,
+    /// The version of the tool that last wrote the archive
+    pub tool_version: String,
+    /// The version of the tool that created the archive
+    pub created_tool_version: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    /// Additional information for users of the archive, like where its events came from
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// The changes of the archived events, oldest first
+    #[serde(default)]
+    pub changes: Vec<Change>,
+}
+
+impl ArchiveInfo {
+    /// The information for a new archive created at the given time
+    pub fn new(now: DateTime<Utc>) -> Self {
+        ArchiveInfo {
+            format: FORMAT_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: now,
+            updated: now,
+            metadata: BTreeMap::new(),
+            changes: Vec::new(),
         }
-        Err(error) => Err(format!("Failed to open archive file: {:?}", error))
+    }
+}
+
+#[derive(Deserialize)]
+struct ArchiveContents {
+    #[serde(flatten)]
+    info: ArchiveInfo,
+    months: Months,
+}
+
+#[derive(Serialize)]
+struct ArchiveContentsRef<'a> {
+    #[serde(flatten)]
+    info: &'a ArchiveInfo,
+    months: &'a Months,
+}
+
+/// Reads an archive written by [`write_archive`], migrating archives of older versions.
+pub fn read_archive<P: AsRef<Path>>(archive_path: P) -> Result<(ArchiveInfo, Months), Error> {
+    let archive_file = File::open(archive_path)
+        .map_err(|error| Error::Archive(format!("Failed to open archive file: {}", error)))?;
+    let archive: Value = serde_json::from_reader(BufReader::new(archive_file))
+        .map_err(|error| Error::Archive(format!("Failed to parse archive: {}", error)))?;
+    parse_archive(archive)
+}
+
+/// Migrates an archive to the current format version and reads it.
+fn parse_archive(mut archive: Value) -> Result<(ArchiveInfo, Months), Error> {
+    let version = match archive.get("format") {
+        Some(format) => format.as_u64()
+            .ok_or_else(|| Error::Archive(format!("Invalid archive format version {}", format)))? as u32,
+        None => 1,
     };
+    if version == 0 || version > FORMAT_VERSION {
+        let tool_version = archive.get("tool_version").and_then(Value::as_str).unwrap_or("unknown");
+        return Err(Error::Archive(format!(
+            "Archive format version {} (written by version {}) is not supported, the latest supported version is {}",
+            version, tool_version, FORMAT_VERSION,
+        )));
+    }
+
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        archive = migration(archive)
+            .map_err(|error| Error::Archive(format!("Failed to migrate archive from format version {}: {}", from_version + 1, error)))?;
+    }
+
+    let contents: ArchiveContents = serde_json::from_value(archive)
+        .map_err(|error| Error::Archive(format!("Failed to parse archive: {}", error)))?;
+    Ok((contents.info, contents.months))
 }
 
-pub fn write_archive<P: AsRef<Path>>(archive_path: P, months: &Months) -> Result<(), String> {
+/// Writes the months as JSON archive, creating missing directories.
+///
+/// The archive is replaced atomically and its previous generation is kept with a `.bak` suffix.
+pub fn write_archive<P: AsRef<Path>>(archive_path: P, info: &ArchiveInfo, months: &Months) -> Result<(), Error> {
     let archive_path = archive_path.as_ref();
-    if let Some(error) = archive_path.parent().and_then(|parent_dir| {
-        create_dir_all(parent_dir).err()
-    }) {
-        return Err(format!("Failed to create archive directory: {:?}", error));
+    create_archive_dir(archive_path)?;
+
+    if archive_path.exists() {
+        fs::copy(archive_path, sibling_path(archive_path, ".bak"))
+            .map_err(|error| Error::Archive(format!("Failed to back up archive file: {}", error)))?;
+    }
+
+    write_atomically(archive_path, |writer| {
+        serde_json::to_writer(writer, &ArchiveContentsRef { info, months }).map_err(io::Error::from)
+    }).map_err(|error| Error::Archive(format!("Failed to write to archive file: {}", error)))
+}
+
+fn create_archive_dir(archive_path: &Path) -> Result<(), Error> {
+    match archive_path.parent() {
+        Some(parent_dir) => create_dir_all(parent_dir)
+            .map_err(|error| Error::Archive(format!("Failed to create archive directory: {}", error))),
+        None => Ok(()),
+    }
+}
+
+/// The JSON archive as part of a [`Pipeline`](crate::pipeline::Pipeline).
+///
+/// A missing archive is treated as empty. An archive that exists but can't be read is never overwritten,
+/// so that it can be repaired or migrated by hand.
+///
+/// The archive is locked through a `.lock` file from loading it until the archive is dropped,
+/// so that concurrent runs don't lose each other's changes.
+pub struct ArchiveFile {
+    pub path: PathBuf,
+    /// Metadata to store in the archive, in addition to the metadata already stored in it
+    pub metadata: BTreeMap<String, String>,
+    info: Option<ArchiveInfo>,
+    /// The archived events, to detect changes against
+    previous: Option<HashMap<String, EventState>>,
+    unreadable: bool,
+    lock: Option<LockFile>,
+    on_lock_wait: fn(&Path),
+}
+
+impl ArchiveFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        ArchiveFile { path: path.into(), metadata: BTreeMap::new(), info: None, previous: None, unreadable: false, lock: None, on_lock_wait: |_| {} }
+    }
+
+    fn lock(&mut self) -> Result<(), Error> {
+        if self.lock.is_none() {
+            create_archive_dir(&self.path)?;
+            let lock_path = sibling_path(&self.path, ".lock");
+            let lock = LockFile::acquire(&lock_path, || (self.on_lock_wait)(&lock_path))
+                .map_err(|error| Error::Archive(format!("Failed to lock archive: {}", error)))?;
+            self.lock = Some(lock);
+        }
+        Ok(())
+    }
+
+    pub fn metadata<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets a function that is called with the path of the lock file if another process holds the lock.
+    pub fn on_lock_wait(mut self, on_lock_wait: fn(&Path)) -> Self {
+        self.on_lock_wait = on_lock_wait;
+        self
+    }
+}
+
+impl EventSource for ArchiveFile {
+    fn load(&mut self, _config: &Config, _diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+        if let Err(error) = self.lock() {
+            self.unreadable = true;
+            return Err(error);
+        }
+        if !self.path.exists() {
+            return Ok(Snapshot::default());
+        }
+
+        match read_archive(&self.path) {
+            Ok((info, months)) => {
+                self.info = Some(info);
+                self.previous = Some(event_states(&months));
+                Ok(Snapshot::from(months))
+            }
+            Err(error) => {
+                self.unreadable = true;
+                Err(error)
+            }
+        }
     }
+}
+
+impl EventSink for ArchiveFile {
+    fn write(&mut self, months: &Months, _config: &Config) -> Result<(), Error> {
+        if self.unreadable {
+            return Err(Error::Archive(format!("Refusing to overwrite the unreadable archive {}", self.path.display())));
+        }
+        self.lock()?;
+
+        let now = Utc::now();
+        let mut info = match &self.info {
+            Some(info) => ArchiveInfo {
+                format: FORMAT_VERSION,
+                tool_version: env!("CARGO_PKG_VERSION").to_string(),
+                updated: now,
+                ..info.clone()
+            },
+            None => ArchiveInfo::new(now),
+        };
+        info.metadata.extend(self.metadata.clone());
+        if let Some(previous) = &self.previous {
+            info.changes.extend(changes_between(previous, &event_states(months), now));
+        }
+        write_archive(&self.path, &info, months)
+    }
+}
+
+/// Migrates a bare archive of months to the versioned envelope.
+///
+/// Also pins the UIDs of events that were archived before UIDs were derived from Rapla reservations,
+/// so that calendar clients keep recognizing them.
+fn migrate_legacy_uids(archive: Value) -> serde_json::Result<Value> {
+    let archive: HashMap<String, Vec<Value>> = serde_json::from_value(archive)?;
+
+    let mut months = Months::new();
+    for (month, events) in archive {
+        let mut month_events = Vec::with_capacity(events.len());
+        for event in events {
+            let is_legacy = event.get("legacy_uid").is_none();
+            let mut event: Event = serde_json::from_value(event)?;
+            if is_legacy {
+                event.legacy_uid = Some(format!("{}@icalnigma", event.legacy_hash()));
+            }
+            month_events.push(event);
+        }
+        months.insert(month, month_events);
+    }
+
+    let now = Utc::now();
+    let mut info = ArchiveInfo::new(now);
+    info.format = 2;
+    info.tool_version = "unknown".to_string();
+    info.created_tool_version = "unknown".to_string();
+    serde_json::to_value(ArchiveContentsRef { info: &info, months: &months })
+}
+
+/// Merges a freshly loaded snapshot into the archived months, see [`merge_events`].
+///
+/// Events that keep their UID but change their time, location or title get their sequence incremented,
+/// so that calendar clients pick up the change. New and changed events are marked as modified at the given time.
+pub fn merge_archive(archive_months: Months, mut snapshot: Snapshot, now: DateTime<Utc>, cutoff: DateTime<Utc>) -> Months {
+    carry_over_legacy_uids(&archive_months, &mut snapshot.months);
+
+    let archived_events: Vec<&Event> = archive_months.values().flatten().collect();
+    let archived_uids = assign_uids(&archived_events);
+    let appointments: Vec<(String, Appointment)> = archived_uids.iter()
+        .zip(&archived_events)
+        .filter_map(|(uid, event)| Some((uid.clone(), Appointment::of(event)?)))
+        .collect();
+    let mut revisions: HashMap<String, (u32, Option<DateTime<Utc>>, Revision)> = archived_uids.into_iter()
+        .zip(&archived_events)
+        .map(|(uid, event)| (uid, (event.sequence, event.last_modified, Revision::of(event))))
+        .collect();
+
+    let mut merged_months = merge_events(archive_months, snapshot, cutoff);
+    carry_over_rescheduled_appointments(appointments, &revisions.keys().collect(), &mut merged_months);
+
+    let merged_events: Vec<&Event> = merged_months.values().flatten().collect();
+    let merged_uids = assign_uids(&merged_events);
+    for (event, uid) in merged_months.values_mut().flatten().zip(merged_uids) {
+        match revisions.remove(&uid) {
+            Some((sequence, last_modified, revision)) => {
+                if revision == Revision::of(event) {
+                    event.sequence = sequence;
+                    event.last_modified = last_modified;
+                } else {
+                    event.sequence = sequence + 1;
+                    event.last_modified = Some(now);
+                }
+            }
+            None => {
+                event.sequence = 0;
+                event.last_modified = Some(now);
+            }
+        }
+    }
+
+    merged_months
+}
+
+/// Merges a freshly loaded snapshot into the archived months event by event.
+///
+/// Events that began before the cutoff are frozen: archived events are kept even if the input doesn't contain them anymore,
+/// and take precedence over loaded events with the same [`Event::key`]. Past events that are missing in the archive are added.
+/// Events from the cutoff on are taken from the input for the dates it covers, so that cancellations are picked up.
+/// Archived events of other dates are kept.
+pub fn merge_events(mut archive_months: Months, snapshot: Snapshot, cutoff: DateTime<Utc>) -> Months {
+    for events in archive_months.values_mut() {
+        events.retain(|event| event.begin < cutoff || !snapshot.covers(event));
+    }
+    let frozen_keys: HashSet<_> = archive_months.values().flatten()
+        .filter(|event| event.begin < cutoff)
+        .map(Event::key)
+        .collect();
+
+    for (month, events) in snapshot.months {
+        archive_months.entry(month).or_default()
+            .extend(events.into_iter().filter(|event| event.begin >= cutoff || !frozen_keys.contains(&event.key())));
+    }
+    for events in archive_months.values_mut() {
+        sort_events(events);
+    }
+    archive_months
+}
+
+/// A change of an event, detected when the archive was updated
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Change {
+    pub detected: DateTime<Utc>,
+    pub uid: String,
+    pub name: String,
+    #[serde(default)]
+    pub courses: Vec<String>,
+    /// The begin of the event after the change, or before it was removed
+    pub begin: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: ChangeKind,
+}
+
+/// What changed about an event, with the old and new values
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Rescheduled {
+        old_begin: DateTime<Utc>,
+        old_end: DateTime<Utc>,
+        new_begin: DateTime<Utc>,
+        new_end: DateTime<Utc>,
+    },
+    Relocated {
+        old_locations: Vec<String>,
+        new_locations: Vec<String>,
+    },
+    Renamed {
+        old_name: String,
+        new_name: String,
+    },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let local = |time: DateTime<Utc>| time.with_timezone(&Berlin).format("%d.%m.%Y %H:%M");
+        write!(f, "{} ", local(self.detected))?;
+        match &self.kind {
+            ChangeKind::Added => write!(f, "added {} on {}", self.name, local(self.begin))?,
+            ChangeKind::Removed => write!(f, "removed {} on {}", self.name, local(self.begin))?,
+            ChangeKind::Rescheduled { old_begin, old_end, new_begin, new_end } => write!(
+                f, "rescheduled {} from {} - {} to {} - {}",
+                self.name, local(*old_begin), local(*old_end), local(*new_begin), local(*new_end),
+            )?,
+            ChangeKind::Relocated { old_locations, new_locations } => write!(
+                f, "relocated {} on {} from \"{}\" to \"{}\"",
+                self.name, local(self.begin), old_locations.join(", "), new_locations.join(", "),
+            )?,
+            ChangeKind::Renamed { old_name, new_name } => write!(
+                f, "renamed \"{}\" on {} to \"{}\"", old_name, local(self.begin), new_name,
+            )?,
+        }
+        if !self.courses.is_empty() {
+            write!(f, " ({})", self.courses.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Which changes to list from the change log, every given criterion has to match
+#[derive(Default)]
+pub struct ChangeQuery {
+    /// A course of the event, ignoring case
+    pub course: Option<String>,
+    /// A part of the name of the event, ignoring case
+    pub name: Option<String>,
+    /// The earliest begin of the event
+    pub from: Option<DateTime<Utc>>,
+    /// The latest begin of the event, exclusive
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl ChangeQuery {
+    /// Checks whether a change matches. Rescheduled events match if either their old or their new begin is in range.
+    pub fn matches(&self, change: &Change) -> bool {
+        let course_matches = self.course.as_ref().is_none_or(|course| {
+            change.courses.iter().any(|change_course| change_course.eq_ignore_ascii_case(course))
+        });
+        let name_matches = self.name.as_ref().is_none_or(|name| {
+            let name = name.to_lowercase();
+            let old_name_matches = match &change.kind {
+                ChangeKind::Renamed { old_name, .. } => old_name.to_lowercase().contains(&name),
+                _ => false,
+            };
+            old_name_matches || change.name.to_lowercase().contains(&name)
+        });
+        let in_range = |begin: DateTime<Utc>| {
+            self.from.is_none_or(|from| begin >= from) && self.to.is_none_or(|to| begin < to)
+        };
+        let date_matches = in_range(change.begin) || match change.kind {
+            ChangeKind::Rescheduled { old_begin, .. } => in_range(old_begin),
+            _ => false,
+        };
+        course_matches && name_matches && date_matches
+    }
+}
+
+/// Lists the changes between archived and merged events, see [`merge_archive`].
+/// Events are matched by their UIDs, which identify appointments by their reservation and original date (see [`assign_uids`]),
+/// so that cancelling one appointment doesn't affect the others. An event can have several changes at once.
+pub fn detect_changes(archive_months: &Months, months: &Months, detected: DateTime<Utc>) -> Vec<Change> {
+    changes_between(&event_states(archive_months), &event_states(months), detected)
+}
+
+/// The properties of an event that the change log tracks
+struct EventState {
+    name: String,
+    courses: Vec<String>,
+    begin: DateTime<Utc>,
+    end: DateTime<Utc>,
+    locations: Vec<String>,
+}
+
+fn event_states(months: &Months) -> HashMap<String, EventState> {
+    let events: Vec<&Event> = months.values().flatten().collect();
+    assign_uids(&events).into_iter().zip(events).map(|(uid, event)| (uid, EventState {
+        name: event.name.clone(),
+        courses: event.courses.clone(),
+        begin: event.begin,
+        end: event.end,
+        locations: event.locations.clone(),
+    })).collect()
+}
 
-    return match OpenOptions::new().write(true).truncate(true).read(false).create(true).open(archive_path) {
-        Ok(archive_file) => {
-            serde_json::to_writer(archive_file, months).map_err(|error|
-                format!("Failed to convert archive to JSON: {:?}", error)
-            )
+fn changes_between(previous: &HashMap<String, EventState>, current: &HashMap<String, EventState>, detected: DateTime<Utc>) -> Vec<Change> {
+    let change = |uid: &str, state: &EventState, kind: ChangeKind| Change {
+        detected,
+        uid: uid.to_string(),
+        name: state.name.clone(),
+        courses: state.courses.clone(),
+        begin: state.begin,
+        kind,
+    };
+
+    let mut changes = Vec::new();
+    for (uid, state) in current {
+        let old = match previous.get(uid) {
+            Some(old) => old,
+            None => {
+                changes.push(change(uid, state, ChangeKind::Added));
+                continue;
+            }
+        };
+        if (old.begin, old.end) != (state.begin, state.end) {
+            changes.push(change(uid, state, ChangeKind::Rescheduled {
+                old_begin: old.begin,
+                old_end: old.end,
+                new_begin: state.begin,
+                new_end: state.end,
+            }));
         }
-        Err(error) => {
-            Err(format!("Failed to write to archive file: {:?}", error))
+        if old.locations != state.locations {
+            changes.push(change(uid, state, ChangeKind::Relocated {
+                old_locations: old.locations.clone(),
+                new_locations: state.locations.clone(),
+            }));
+        }
+        if old.name != state.name {
+            changes.push(change(uid, state, ChangeKind::Renamed {
+                old_name: old.name.clone(),
+                new_name: state.name.clone(),
+            }));
+        }
+    }
+    for (uid, state) in previous {
+        if !current.contains_key(uid) {
+            changes.push(change(uid, state, ChangeKind::Removed));
+        }
+    }
+    changes.sort_by(|a, b| a.begin.cmp(&b.begin).then_with(|| a.uid.cmp(&b.uid)));
+    changes
+}
+
+/// The properties of an event that calendar clients need to be notified about
+#[derive(PartialEq)]
+struct Revision {
+    begin: DateTime<Utc>,
+    end: DateTime<Utc>,
+    locations: Vec<String>,
+    title: String,
+}
+
+impl Revision {
+    fn of(event: &Event) -> Self {
+        Revision {
+            begin: event.begin,
+            end: event.end,
+            locations: event.locations.clone(),
+            title: event.title(),
         }
+    }
+}
+
+/// Hands the identity of archived appointments that vanished from the merged months down to the new appointments
+/// of the same reservation, as these are the vanished appointments rescheduled to another day.
+/// Each new appointment takes the vanished appointment that began closest to it, the others have been cancelled.
+fn carry_over_rescheduled_appointments(appointments: Vec<(String, Appointment)>, archived_uids: &HashSet<&String>, merged_months: &mut Months) {
+    let merged_uids = {
+        let merged_events: Vec<&Event> = merged_months.values().flatten().collect();
+        assign_uids(&merged_events)
     };
+    let current_uids: HashSet<&String> = merged_uids.iter().collect();
+
+    let mut vanished: HashMap<String, Vec<Appointment>> = HashMap::new();
+    for (uid, appointment) in appointments {
+        if !current_uids.contains(&uid) {
+            vanished.entry(appointment.reservation.clone()).or_default().push(appointment);
+        }
+    }
+    for (event, uid) in merged_months.values_mut().flatten().zip(&merged_uids) {
+        if archived_uids.contains(uid) {
+            continue;
+        }
+        let rescheduled = event.reservation.as_deref()
+            .and_then(|reservation| vanished.get_mut(reservation))
+            .and_then(|appointments| {
+                let nearest = (0..appointments.len())
+                    .min_by_key(|index| (appointments[*index].begin - event.begin).num_seconds().abs())?;
+                Some(appointments.swap_remove(nearest))
+            });
+        if let Some(rescheduled) = rescheduled {
+            event.original_date = Some(rescheduled.date);
+            if event.legacy_uid.is_none() {
+                event.legacy_uid = rescheduled.legacy_uid;
+            }
+        }
+    }
+}
+
+/// What identifies an archived reservation appointment
+struct Appointment {
+    reservation: String,
+    begin: DateTime<Utc>,
+    date: NaiveDate,
+    legacy_uid: Option<String>,
+}
+
+impl Appointment {
+    fn of(event: &Event) -> Option<Self> {
+        Some(Appointment {
+            reservation: event.reservation.clone()?,
+            begin: event.begin,
+            date: event.appointment_date(),
+            legacy_uid: event.legacy_uid.clone(),
+        })
+    }
+}
+
+/// Hands the legacy UIDs of archived events down to the matching freshly loaded events.
+/// Events are matched by their name and begin, as the legacy UIDs were mostly derived from these.
+fn carry_over_legacy_uids(archive_months: &Months, months: &mut Months) {
+    let legacy_uids: HashMap<(&str, DateTime<Utc>), &String> = archive_months.values().flatten()
+        .filter_map(|event| Some(((event.name.as_str(), event.begin), event.legacy_uid.as_ref()?)))
+        .collect();
+
+    for event in months.values_mut().flatten() {
+        if event.legacy_uid.is_none() {
+            event.legacy_uid = legacy_uids.get(&(event.name.as_str(), event.begin)).map(|uid| uid.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, TimeZone};
+
+    use crate::model::{DateRange, test_event};
+    use crate::util::test_directory;
+
+    use super::*;
+
+    fn event(reservation: &str, day: u32, location: &str) -> Event {
+        Event {
+            name: format!("Lecture {}", reservation),
+            locations: vec![location.to_string()],
+            reservation: Some(reservation.to_string()),
+            ..test_event(Utc.ymd(2023, 3, day).and_hms(8, 0, 0), Utc.ymd(2023, 3, day).and_hms(10, 0, 0))
+        }
+    }
+
+    fn months(events: Vec<Event>) -> Months {
+        let mut months = Months::new();
+        months.insert("202303".to_string(), events);
+        months
+    }
+
+    fn summary(months: &Months) -> Vec<(String, u32, String)> {
+        months.values().flatten()
+            .map(|event| (event.name.clone(), event.begin.day(), event.locations.join(", ")))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_past_events_missing_from_input() {
+        let cutoff = Utc.ymd(2023, 3, 15).and_hms(0, 0, 0);
+        let archive = months(vec![event("a", 1, "A 1"), event("b", 20, "B 1")]);
+        let input = months(vec![event("b", 20, "B 1")]);
+
+        let merged = merge_events(archive, Snapshot::from(input), cutoff);
+        assert_eq!(summary(&merged), vec![
+            ("Lecture a".to_string(), 1, "A 1".to_string()),
+            ("Lecture b".to_string(), 20, "B 1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn takes_future_events_from_input() {
+        let cutoff = Utc.ymd(2023, 3, 15).and_hms(0, 0, 0);
+        let archive = months(vec![event("a", 20, "A 1"), event("b", 21, "B 1")]);
+        let input = months(vec![event("b", 21, "B 2"), event("c", 22, "C 1")]);
+
+        let merged = merge_events(archive, Snapshot::from(input), cutoff);
+        assert_eq!(summary(&merged), vec![
+            ("Lecture b".to_string(), 21, "B 2".to_string()),
+            ("Lecture c".to_string(), 22, "C 1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn freezes_archived_past_events() {
+        let cutoff = Utc.ymd(2023, 3, 15).and_hms(0, 0, 0);
+        let archive = months(vec![event("a", 1, "A 1")]);
+        let input = months(vec![event("a", 1, "A 2"), event("b", 2, "B 1")]);
+
+        let merged = merge_events(archive, Snapshot::from(input), cutoff);
+        assert_eq!(summary(&merged), vec![
+            ("Lecture a".to_string(), 1, "A 1".to_string()),
+            ("Lecture b".to_string(), 2, "B 1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn keeps_months_missing_from_input() {
+        let cutoff = Utc.ymd(2023, 3, 15).and_hms(0, 0, 0);
+        let mut archive = months(vec![event("a", 20, "A 1")]);
+        archive.insert("202302".to_string(), vec![event("old", 1, "X")]);
+        let input = months(vec![event("a", 20, "A 1")]);
+
+        let merged = merge_events(archive, Snapshot::from(input), cutoff);
+        assert_eq!(merged.keys().collect::<Vec<_>>(), vec!["202302", "202303"]);
+        assert_eq!(merged["202302"].len(), 1);
+    }
+
+    #[test]
+    fn empties_months_without_events() {
+        let cutoff = Utc.ymd(2023, 3, 15).and_hms(0, 0, 0);
+        let archive = months(vec![event("a", 1, "A 1"), event("b", 20, "B 1")]);
+
+        let merged = merge_events(archive, Snapshot::from(months(vec![])), cutoff);
+        assert_eq!(summary(&merged), vec![("Lecture a".to_string(), 1, "A 1".to_string())]);
+    }
+
+    #[test]
+    fn keeps_events_outside_of_partial_pages() {
+        let cutoff = Utc.ymd(2023, 1, 1).and_hms(0, 0, 0);
+        let archive = months(vec![event("a", 2, "A 1"), event("a", 9, "A 1"), event("b", 10, "B 1"), event("a", 16, "A 1")]);
+        let week = Snapshot {
+            months: months(vec![event("a", 9, "A 2")]),
+            covered: vec![DateRange { first: NaiveDate::from_ymd(2023, 3, 6), last: NaiveDate::from_ymd(2023, 3, 12) }],
+        };
+
+        let merged = merge_events(archive, week, cutoff);
+        assert_eq!(summary(&merged), vec![
+            ("Lecture a".to_string(), 2, "A 1".to_string()),
+            ("Lecture a".to_string(), 9, "A 2".to_string()),
+            ("Lecture a".to_string(), 16, "A 1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn tracks_rescheduled_events() {
+        let cutoff = Utc.ymd(2023, 3, 15).and_hms(0, 0, 0);
+        let now = Utc.ymd(2023, 3, 15).and_hms(12, 0, 0);
+        let archive = months(vec![event("a", 20, "A 1"), event("b", 21, "B 1")]);
+        let input = months(vec![event("a", 22, "A 1"), event("b", 21, "B 1")]);
+
+        let merged = merge_archive(archive, Snapshot::from(input), now, cutoff);
+        let events: Vec<(u32, u32, Option<DateTime<Utc>>)> = merged["202303"].iter()
+            .map(|event| (event.begin.day(), event.sequence, event.last_modified))
+            .collect();
+        assert_eq!(events, vec![(21, 0, None), (22, 1, Some(now))]);
+    }
+
+    #[test]
+    fn migrates_bare_archives() {
+        let mut legacy = serde_json::to_value(months(vec![event("a", 1, "A 1")])).unwrap();
+        legacy["202303"][0].as_object_mut().unwrap().remove("legacy_uid");
+
+        let (info, migrated) = parse_archive(legacy).unwrap();
+        assert_eq!(info.format, FORMAT_VERSION);
+        assert_eq!(summary(&migrated), vec![("Lecture a".to_string(), 1, "A 1".to_string())]);
+        assert!(migrated["202303"][0].legacy_uid.is_some());
+    }
+
+    #[test]
+    fn keeps_the_creating_tool_version() {
+        let directory = test_directory("archive");
+        let path = directory.join("archive.json");
+        let mut info = ArchiveInfo::new(Utc.ymd(2023, 3, 1).and_hms(0, 0, 0));
+        info.tool_version = "0.1.0".to_string();
+        info.created_tool_version = "0.1.0".to_string();
+        write_archive(&path, &info, &months(vec![event("a", 1, "A 1")])).unwrap();
+
+        let mut archive = ArchiveFile::new(&path);
+        let snapshot = archive.load(&Config::default(), &mut Diagnostics::new()).unwrap();
+        archive.write(&snapshot.months, &Config::default()).unwrap();
+        drop(archive);
+
+        let (info, _) = read_archive(&path).unwrap();
+        assert_eq!(info.tool_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.created_tool_version, "0.1.0");
+        assert_eq!(info.created, Utc.ymd(2023, 3, 1).and_hms(0, 0, 0));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_previous_archive_as_backup() {
+        let directory = test_directory("backup");
+        let path = directory.join("archive.json");
+        let info = ArchiveInfo::new(Utc.ymd(2023, 3, 1).and_hms(0, 0, 0));
+        write_archive(&path, &info, &months(vec![event("a", 1, "A 1")])).unwrap();
+        assert!(!sibling_path(&path, ".bak").exists());
+        write_archive(&path, &info, &months(vec![event("b", 2, "B 2")])).unwrap();
+
+        let (_, backup) = read_archive(sibling_path(&path, ".bak")).unwrap();
+        assert_eq!(summary(&backup), vec![("Lecture a".to_string(), 1, "A 1".to_string())]);
+        let (_, current) = read_archive(&path).unwrap();
+        assert_eq!(summary(&current), vec![("Lecture b".to_string(), 2, "B 2".to_string())]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn reads_current_archives() {
+        let info = ArchiveInfo::new(Utc.ymd(2023, 3, 1).and_hms(0, 0, 0));
+        let archive = months(vec![event("a", 1, "A 1")]);
+        let value = serde_json::to_value(ArchiveContentsRef { info: &info, months: &archive }).unwrap();
+
+        let (read_info, read_months) = parse_archive(value).unwrap();
+        assert_eq!(read_info.created, info.created);
+        assert_eq!(summary(&read_months), summary(&archive));
+        assert_eq!(read_months["202303"][0].legacy_uid, None);
+    }
+
+    #[test]
+    fn rejects_newer_archives() {
+        let value = serde_json::json!({ "format": FORMAT_VERSION + 1, "tool_version": "9.0.0", "months": {} });
+        assert!(matches!(parse_archive(value), Err(Error::Archive(_))));
+    }
+
+    #[test]
+    fn detects_changes() {
+        let detected = Utc.ymd(2023, 3, 10).and_hms(0, 0, 0);
+        let archive = months(vec![event("a", 1, "A 1"), event("b", 20, "B 1"), event("c", 21, "C 1")]);
+        let mut renamed = event("c", 21, "C 1");
+        renamed.name = "Lecture c2".to_string();
+        let mut rescheduled = event("b", 22, "B 1");
+        rescheduled.original_date = Some(NaiveDate::from_ymd(2023, 3, 20));
+        let merged = months(vec![event("a", 1, "A 2"), rescheduled, renamed, event("d", 23, "D 1")]);
+
+        let kinds: Vec<(String, ChangeKind)> = detect_changes(&archive, &merged, detected).into_iter()
+            .map(|change| (change.name, change.kind))
+            .collect();
+        assert_eq!(kinds, vec![
+            ("Lecture a".to_string(), ChangeKind::Relocated { old_locations: vec!["A 1".to_string()], new_locations: vec!["A 2".to_string()] }),
+            ("Lecture c2".to_string(), ChangeKind::Renamed { old_name: "Lecture c".to_string(), new_name: "Lecture c2".to_string() }),
+            ("Lecture b".to_string(), ChangeKind::Rescheduled {
+                old_begin: Utc.ymd(2023, 3, 20).and_hms(8, 0, 0),
+                old_end: Utc.ymd(2023, 3, 20).and_hms(10, 0, 0),
+                new_begin: Utc.ymd(2023, 3, 22).and_hms(8, 0, 0),
+                new_end: Utc.ymd(2023, 3, 22).and_hms(10, 0, 0),
+            }),
+            ("Lecture d".to_string(), ChangeKind::Added),
+        ]);
+    }
+
+    /// Merges the input into the archive and lists the changes by the day of their events.
+    fn changes(archive: &dyn Fn() -> Vec<Event>, input: Vec<Event>) -> Vec<(u32, ChangeKind)> {
+        let cutoff = Utc.ymd(2023, 1, 1).and_hms(0, 0, 0);
+        let merged = merge_archive(months(archive()), Snapshot::from(months(input)), cutoff, cutoff);
+        detect_changes(&months(archive()), &merged, cutoff).into_iter()
+            .map(|change| (change.begin.day(), change.kind))
+            .collect()
+    }
+
+    fn series() -> Vec<Event> {
+        vec![event("a", 2, "A 1"), event("a", 9, "A 1"), event("a", 16, "A 1")]
+    }
+
+    #[test]
+    fn logs_cancelled_appointments_of_a_series() {
+        assert_eq!(changes(&series, vec![event("a", 9, "A 1"), event("a", 16, "A 1")]), vec![(2, ChangeKind::Removed)]);
+        assert_eq!(changes(&series, vec![event("a", 2, "A 1"), event("a", 16, "A 1")]), vec![(9, ChangeKind::Removed)]);
+    }
+
+    #[test]
+    fn pairs_rescheduled_appointments_with_the_nearest_vanished_one() {
+        assert_eq!(changes(&series, vec![event("a", 2, "A 1"), event("a", 17, "A 1")]), vec![
+            (9, ChangeKind::Removed),
+            (17, ChangeKind::Rescheduled {
+                old_begin: Utc.ymd(2023, 3, 16).and_hms(8, 0, 0),
+                old_end: Utc.ymd(2023, 3, 16).and_hms(10, 0, 0),
+                new_begin: Utc.ymd(2023, 3, 17).and_hms(8, 0, 0),
+                new_end: Utc.ymd(2023, 3, 17).and_hms(10, 0, 0),
+            }),
+        ]);
+    }
+
+    #[test]
+    fn queries_changes() {
+        let mut removed = event("a", 20, "A 1");
+        removed.courses = vec!["TIN-21B3".to_string()];
+        let archive = months(vec![removed, event("b", 1, "B 1")]);
+        let merged = months(vec![event("b", 25, "B 1")]);
+        let changes = detect_changes(&archive, &merged, Utc.ymd(2023, 3, 10).and_hms(0, 0, 0));
+
+        let query = ChangeQuery { course: Some("tin-21b3".to_string()), ..ChangeQuery::default() };
+        assert_eq!(changes.iter().filter(|change| query.matches(change)).count(), 1);
+        let query = ChangeQuery {
+            name: Some("lecture B".to_string()),
+            from: Some(Utc.ymd(2023, 3, 1).and_hms(0, 0, 0)),
+            to: Some(Utc.ymd(2023, 3, 2).and_hms(0, 0, 0)),
+            ..ChangeQuery::default()
+        };
+        assert_eq!(changes.iter().filter(|change| query.matches(change)).count(), 1);
+    }
 }
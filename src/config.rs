@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as _;
+
+use crate::util::Error;
+
+/// Site specific settings that can be loaded from a JSON file.
+///
+/// ```json
+/// {
+///     "resources": [
+///         { "pattern": "^[A-Z]{3}-[A-Z0-9 ]+$", "kind": "course" },
+///         { "pattern": "^(Online|MS Teams)$", "kind": "online" }
+///     ],
+///     "event_types": [
+///         { "title": "(?i)^klausur", "kind": "exam" },
+///         { "art": "^Sprechstunde$", "kind": "consultation" }
+///     ]
+/// }
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Ordered rules that classify the resources of an event, the first matching rule applies.
+    /// Resources that don't match any rule are rooms.
+    #[serde(default = "default_resource_rules")]
+    pub resources: Vec<ResourceRule>,
+    /// Ordered rules that decide the type of an event, the first matching rule applies.
+    /// Events that don't match any rule are lectures if they have a location.
+    #[serde(default = "default_event_type_rules")]
+    pub event_types: Vec<EventTypeRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceRule {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    pub kind: ResourceKind,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceKind {
+    Course,
+    Room,
+    /// A marker resource for events that take place online, treated like a room
+    Online,
+    Lecturer,
+}
+
+/// A rule that matches if all of its given patterns match.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventTypeRule {
+    /// Matched against the title of the event
+    #[serde(default, deserialize_with = "deserialize_optional_regex")]
+    pub title: Option<Regex>,
+    /// Matched against the "Art" field of Rapla
+    #[serde(default, deserialize_with = "deserialize_optional_regex")]
+    pub art: Option<Regex>,
+    /// Matched against each resource of the event
+    #[serde(default, deserialize_with = "deserialize_optional_regex")]
+    pub resource: Option<Regex>,
+    pub kind: EventKind,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Lecture,
+    Exam,
+    Presentation,
+    Holiday,
+    Consultation,
+    Other,
+}
+
+impl EventTypeRule {
+    fn matches(&self, title: &str, art: Option<&str>, resources: &[String]) -> bool {
+        self.title.as_ref().is_none_or(|pattern| pattern.is_match(title))
+            && self.art.as_ref().is_none_or(|pattern| art.is_some_and(|art| pattern.is_match(art)))
+            && self.resource.as_ref().is_none_or(|pattern| resources.iter().any(|resource| pattern.is_match(resource)))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            resources: default_resource_rules(),
+            event_types: default_event_type_rules(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration from a JSON file. Missing settings keep their defaults.
+    pub fn load(path: &str) -> Result<Config, Error> {
+        let file = File::open(path)
+            .map_err(|error| Error::Config(format!("Failed to open {}: {}", path, error)))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|error| Error::Config(format!("Failed to read {}: {}", path, error)))
+    }
+
+    /// Determines what the given resource is, based on the first matching rule.
+    pub fn classify_resource(&self, resource: &str) -> ResourceKind {
+        self.resources.iter()
+            .find(|rule| rule.pattern.is_match(resource))
+            .map_or(ResourceKind::Room, |rule| rule.kind)
+    }
+
+    /// Determines the type of an event, if any rule matches.
+    pub fn classify_event(&self, title: &str, art: Option<&str>, resources: &[String]) -> Option<EventKind> {
+        self.event_types.iter()
+            .find(|rule| rule.matches(title, art, resources))
+            .map(|rule| rule.kind)
+    }
+
+    /// Checks whether any of the given locations marks an online event.
+    pub fn is_online<S: AsRef<str>>(&self, locations: &[S]) -> bool {
+        locations.iter().any(|location| self.classify_resource(location.as_ref()) == ResourceKind::Online)
+    }
+}
+
+fn default_resource_rules() -> Vec<ResourceRule> {
+    vec![
+        ResourceRule { pattern: Regex::new(r"^[A-Z]{3}-[A-Z0-9 ]+$").unwrap(), kind: ResourceKind::Course },
+        ResourceRule { pattern: Regex::new(r"^Online-Vorlesung$").unwrap(), kind: ResourceKind::Online },
+    ]
+}
+
+fn default_event_type_rules() -> Vec<EventTypeRule> {
+    let rule = |title: &str, kind: EventKind| EventTypeRule {
+        title: Some(Regex::new(title).unwrap()),
+        art: None,
+        resource: None,
+        kind,
+    };
+    vec![
+        rule(r"(?i)klausureinsicht|sprechstunde|konsultation|exam review|consultation|office hours", EventKind::Consultation),
+        rule(r"(?i)^(nach)?klausur\b|^prüfung\b|^(re-?)?exam(ination)?\b", EventKind::Exam),
+        rule(r"(?i)^(projekt)?präsentation\b|^presentation\b|^verteidigung\b|^defen[cs]e\b", EventKind::Presentation),
+        rule(r"(?i)^(vorlesungsfrei|feiertag|lecture[- ]free|no lectures|holiday)", EventKind::Holiday),
+        EventTypeRule {
+            title: None,
+            art: Some(Regex::new(r"(?i)^(klausur|prüfung|exam)").unwrap()),
+            resource: None,
+            kind: EventKind::Exam,
+        },
+    ]
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(D::Error::custom)
+}
+
+fn deserialize_optional_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    deserialize_regex(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::util::test_directory;
+
+    use super::*;
+
+    fn load_json(name: &str, json: &str) -> Result<Config, Error> {
+        let directory = test_directory(&format!("config-{}", name));
+        let path = directory.join("config.json");
+        fs::write(&path, json).unwrap();
+        let config = Config::load(path.to_str().unwrap());
+        fs::remove_dir_all(directory).unwrap();
+        config
+    }
+
+    #[test]
+    fn classifies_resources_by_default() {
+        let config = Config::default();
+        assert_eq!(config.classify_resource("TIN-21B3"), ResourceKind::Course);
+        assert_eq!(config.classify_resource("WWI-22 SCA"), ResourceKind::Course);
+        assert_eq!(config.classify_resource("Online-Vorlesung"), ResourceKind::Online);
+        assert_eq!(config.classify_resource("TINF21B3"), ResourceKind::Room);
+        assert_eq!(config.classify_resource("A 1.23"), ResourceKind::Room);
+        assert!(config.is_online(&["A 1.23", "Online-Vorlesung"]));
+        assert!(!config.is_online(&["A 1.23"]));
+    }
+
+    #[test]
+    fn applies_the_first_matching_resource_rule() {
+        let config = load_json("resources", r#"{
+            "resources": [
+                { "pattern": "^(Online|MS Teams)$", "kind": "online" },
+                { "pattern": "^[A-Z]+[0-9]{2,4}[A-Z][0-9]?$", "kind": "course" },
+                { "pattern": "^(Prof\\. )?Dr\\. ", "kind": "lecturer" },
+                { "pattern": ".", "kind": "room" }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(config.classify_resource("TINF21B3"), ResourceKind::Course);
+        assert_eq!(config.classify_resource("WI2022A"), ResourceKind::Course);
+        assert_eq!(config.classify_resource("MS Teams"), ResourceKind::Online);
+        assert_eq!(config.classify_resource("Prof. Dr. Muster"), ResourceKind::Lecturer);
+        assert_eq!(config.classify_resource("Online-Vorlesung"), ResourceKind::Room);
+        assert_eq!(config.event_types.len(), default_event_type_rules().len());
+    }
+
+    #[test]
+    fn detects_exams_by_default() {
+        let config = Config::default();
+        for title in ["Klausur Mathematik I", "Nachklausur Analysis", "Prüfung Informatik", "Exam Algorithms", "Re-Exam Algorithms", "Reexam Algorithms", "Examination Theory"].iter() {
+            assert_eq!(config.classify_event(title, None, &[]), Some(EventKind::Exam), "{}", title);
+        }
+        for title in ["Klausurvorbereitung", "Examples of Rust", "Vorlesung Klausur"].iter() {
+            assert_eq!(config.classify_event(title, None, &[]), None, "{}", title);
+        }
+        assert_eq!(config.classify_event("Klausureinsicht Mathematik I", None, &[]), Some(EventKind::Consultation));
+        assert_eq!(config.classify_event("Mathematik I", Some("Klausur"), &[]), Some(EventKind::Exam));
+        assert_eq!(config.classify_event("Mathematik I", Some("Vorlesung"), &[]), None);
+    }
+
+    #[test]
+    fn detects_other_event_types_by_default() {
+        let config = Config::default();
+        for title in ["Vorlesungsfrei", "Feiertag: Ostermontag", "Lecture-free week", "No lectures", "Holiday"].iter() {
+            assert_eq!(config.classify_event(title, None, &[]), Some(EventKind::Holiday), "{}", title);
+        }
+        assert_eq!(config.classify_event("Kein Feiertag", None, &[]), None);
+        assert_eq!(config.classify_event("Projektpräsentation", None, &[]), Some(EventKind::Presentation));
+        assert_eq!(config.classify_event("Verteidigung Bachelorarbeit", None, &[]), Some(EventKind::Presentation));
+        assert_eq!(config.classify_event("Präsentationstechniken", None, &[]), None);
+        assert_eq!(config.classify_event("Cyber Defense", None, &[]), None);
+        assert_eq!(config.classify_event("Sprechstunde", None, &[]), Some(EventKind::Consultation));
+        assert_eq!(config.classify_event("Office Hours", None, &[]), Some(EventKind::Consultation));
+        assert_eq!(config.classify_event("Mathematik I", None, &[]), None);
+    }
+
+    #[test]
+    fn requires_all_patterns_of_event_type_rules() {
+        let config = load_json("event-types", r#"{
+            "event_types": [
+                { "art": "^Klausur$", "resource": "^Audimax$", "kind": "exam" },
+                { "title": "(?i)^tutorium", "kind": "other" }
+            ]
+        }"#).unwrap();
+        let audimax = vec!["Audimax".to_string()];
+
+        assert_eq!(config.classify_event("Mathematik I", Some("Klausur"), &audimax), Some(EventKind::Exam));
+        assert_eq!(config.classify_event("Mathematik I", Some("Klausur"), &[]), None);
+        assert_eq!(config.classify_event("Mathematik I", None, &audimax), None);
+        assert_eq!(config.classify_event("Tutorium Mathematik", None, &[]), Some(EventKind::Other));
+        assert_eq!(config.classify_event("Klausur Mathematik I", None, &[]), None);
+        assert_eq!(config.resources.len(), default_resource_rules().len());
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(matches!(load_json("pattern", r#"{ "resources": [{ "pattern": "(", "kind": "room" }] }"#), Err(Error::Config(_))));
+        assert!(matches!(load_json("kind", r#"{ "resources": [{ "pattern": "x", "kind": "kitchen" }] }"#), Err(Error::Config(_))));
+        assert!(matches!(load_json("field", r#"{ "rooms": [] }"#), Err(Error::Config(_))));
+        assert!(matches!(load_json("event-type", r#"{ "event_types": [{ "title": "x" }] }"#), Err(Error::Config(_))));
+        assert!(matches!(Config::load("/nonexistent/icalnigma.json"), Err(Error::Config(_))));
+    }
+}
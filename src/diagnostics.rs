@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+
+use serde::Serialize;
+
+use crate::model::Months;
+use crate::util::{Error, Location};
+
+/// The kinds of problems that make the parser skip parts of the input
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// A month calendar that couldn't be assigned to a month
+    SkippedCalendar,
+    /// A day cell of a month that couldn't be read at all
+    SkippedCell,
+    /// An event or list row that couldn't be parsed
+    UnparseableEvent,
+    /// An element in a day cell that doesn't have the class of an event
+    UnknownClass,
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DiagnosticKind::SkippedCalendar => "skipped calendar",
+            DiagnosticKind::SkippedCell => "skipped cell",
+            DiagnosticKind::UnparseableEvent => "unparseable event",
+            DiagnosticKind::UnknownClass => "unknown class",
+        })
+    }
+}
+
+/// A single problem that the parser worked around
+#[derive(Serialize, Debug, Clone)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    #[serde(flatten)]
+    pub location: Location,
+}
+
+/// Collects the problems encountered while parsing, so that they can be reported in one place.
+#[derive(Serialize, Debug, Default)]
+pub struct Diagnostics {
+    pub problems: Vec<Diagnostic>,
+    /// The number of events in each month that the input covered, including months without events
+    pub months: BTreeMap<String, usize>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, kind: DiagnosticKind, message: String, location: Location) {
+        self.problems.push(Diagnostic { kind, message, location });
+    }
+
+    /// Records an error that the parser recovered from.
+    pub fn record(&mut self, kind: DiagnosticKind, error: Error) {
+        match error {
+            Error::Parse(message, location) => self.push(kind, message, location),
+            error => self.push(kind, error.to_string(), Location::default()),
+        }
+    }
+
+    /// Records which months were loaded, so that the report tells empty months apart from missing ones.
+    pub fn record_months(&mut self, months: &Months) {
+        for (month, events) in months {
+            *self.months.entry(month.clone()).or_default() += events.len();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Prints the months without events, every problem and the number of problems per kind to stderr.
+    pub fn print_summary(&self) {
+        let empty_months: Vec<&str> = self.months.iter()
+            .filter(|(_, events)| **events == 0)
+            .map(|(month, _)| month.as_str())
+            .collect();
+        if !empty_months.is_empty() {
+            eprintln!("No events in months: {}", empty_months.join(", "));
+        }
+
+        if self.is_empty() {
+            return;
+        }
+
+        for problem in &self.problems {
+            eprintln!("{}: {}{}", problem.kind, problem.message, problem.location);
+        }
+
+        let mut counts: BTreeMap<DiagnosticKind, usize> = BTreeMap::new();
+        for problem in &self.problems {
+            *counts.entry(problem.kind).or_default() += 1;
+        }
+        let counts: Vec<String> = counts.iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        eprintln!("Skipped parts of the input: {}", counts.join(", "));
+    }
+
+    /// Writes all problems as JSON to the given file.
+    pub fn write_report(&self, path: &str) -> Result<(), Error> {
+        let file = File::create(path)
+            .map_err(|error| Error::Output(format!("Failed to create report {}: {}", path, error)))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|error| Error::Output(format!("Failed to write report {}: {}", path, error)))
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDate};
+use encoding_rs::Encoding;
+
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::input::load_page;
+use crate::model::Snapshot;
+use crate::pipeline::EventSource;
+use crate::util::{Error, Month, Year};
+
+/// How politely and persistently pages are requested from Rapla
+pub struct FetchOptions {
+    /// The timeout for connecting and for each read
+    pub timeout: Duration,
+    /// How often a failed request is repeated
+    pub retries: u32,
+    /// The wait before the first retry, doubled for every further retry
+    pub backoff: Duration,
+    /// The wait between two successive requests
+    pub delay: Duration,
+    /// Called with the URL, the error and the wait before each retry, so that the caller can report it
+    pub on_retry: fn(&str, &str, Duration),
+}
+
+/// A page downloaded from Rapla
+pub struct Page {
+    pub url: String,
+    pub body: Vec<u8>,
+    /// The encoding announced by the server, detected from the page itself if not given
+    pub encoding: Option<&'static Encoding>,
+}
+
+impl EventSource for Page {
+    fn load(&mut self, config: &Config, diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+        load_page(&self.url, &mut self.body.as_slice(), self.encoding, config, diagnostics)
+    }
+}
+
+/// Builds the URL of the month view of a course calendar.
+pub fn month_url(base_url: &str, key: &str, year: Year, month: Month) -> String {
+    let separator = if base_url.contains('?') { '&' } else { '?' };
+    format!("{}{}key={}&day=1&month={}&year={}", base_url, separator, encode_query_value(key), month, year)
+}
+
+/// Lists the first days of all months from the month of `from` to the month of `to`.
+pub fn months_between(from: NaiveDate, to: NaiveDate) -> Vec<(Year, Month)> {
+    let mut months = Vec::new();
+    let (mut year, mut month) = (from.year(), from.month());
+    while (year, month) <= (to.year(), to.month()) {
+        months.push((year, month));
+        if month == 12 {
+            year += 1;
+            month = 1;
+        } else {
+            month += 1;
+        }
+    }
+    months
+}
+
+/// Downloads the month views of a course calendar for all months in the given date range.
+pub fn fetch_months(base_url: &str, key: &str, from: NaiveDate, to: NaiveDate, options: &FetchOptions) -> Result<Vec<Page>, Error> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(options.timeout)
+        .timeout_read(options.timeout)
+        .user_agent(concat!("dh_icalnigma/", env!("CARGO_PKG_VERSION")))
+        .build();
+
+    let mut pages = Vec::new();
+    for (index, (year, month)) in months_between(from, to).into_iter().enumerate() {
+        if index > 0 {
+            thread::sleep(options.delay);
+        }
+        let url = month_url(base_url, key, year, month);
+        let (body, encoding) = fetch_page(&agent, &url, options)?;
+        pages.push(Page { url, body, encoding });
+    }
+    Ok(pages)
+}
+
+/// Requests a single page, retrying with exponential backoff on network errors and server errors.
+fn fetch_page(agent: &ureq::Agent, url: &str, options: &FetchOptions) -> Result<(Vec<u8>, Option<&'static Encoding>), Error> {
+    let mut backoff = options.backoff;
+    let mut attempt = 0;
+    loop {
+        let error = match agent.get(url).call() {
+            Ok(response) => {
+                // ureq defaults to UTF-8 if the server doesn't announce a charset
+                let encoding = response.header("Content-Type")
+                    .filter(|content_type| content_type.to_ascii_lowercase().contains("charset"))
+                    .and_then(|_| Encoding::for_label(response.charset().as_bytes()));
+                let mut body = Vec::new();
+                match response.into_reader().read_to_end(&mut body) {
+                    Ok(_) => return Ok((body, encoding)),
+                    Err(error) => error.to_string(),
+                }
+            }
+            Err(ureq::Error::Status(status, _)) if status != 429 && status < 500 => {
+                return Err(Error::Input(format!("{}: HTTP status {}", url, status)));
+            }
+            Err(error) => error.to_string(),
+        };
+
+        if attempt >= options.retries {
+            return Err(Error::Input(format!("{}: {} (gave up after {} attempts)", url, error, attempt + 1)));
+        }
+        (options.on_retry)(url, &error, backoff);
+        thread::sleep(backoff);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn encode_query_value(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tiny_http::{Response, Server};
+
+    use super::*;
+
+    const FIXTURE: &str = r#"<html><body><div class="calendar"><h2>März 2023</h2><table><tbody><tr>
+<td class="month_cell"><div>2</div><div class="month_block"><a href="/rapla?id=1">08:00 -12:30 TIN-21B3,A 4.12<br>Mathematik</a></div></td>
+</tr></tbody></table></div></body></html>"#;
+
+    fn options() -> FetchOptions {
+        FetchOptions {
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            delay: Duration::ZERO,
+            on_retry: |_, _, _| {},
+        }
+    }
+
+    /// Serves the fixture page, answering the first `failures` requests with a server error.
+    fn serve(failures: usize, requests: usize) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/rapla", server.server_addr().to_ip().unwrap());
+        let urls = Arc::new(Mutex::new(Vec::new()));
+        let recorded_urls = urls.clone();
+        thread::spawn(move || {
+            for (index, request) in server.incoming_requests().take(requests).enumerate() {
+                recorded_urls.lock().unwrap().push(request.url().to_string());
+                let response = if index < failures {
+                    Response::from_string("busy").with_status_code(503)
+                } else {
+                    Response::from_string(FIXTURE)
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        (base_url, urls)
+    }
+
+    #[test]
+    fn builds_month_urls() {
+        assert_eq!(month_url("https://rapla.example/rapla", "a b", 2023, 3), "https://rapla.example/rapla?key=a%20b&day=1&month=3&year=2023");
+        assert_eq!(month_url("https://rapla.example/rapla?page=calendar", "k", 2023, 3), "https://rapla.example/rapla?page=calendar&key=k&day=1&month=3&year=2023");
+    }
+
+    #[test]
+    fn lists_months_across_years() {
+        let months = months_between(NaiveDate::from_ymd(2022, 11, 15), NaiveDate::from_ymd(2023, 2, 1));
+        assert_eq!(months, vec![(2022, 11), (2022, 12), (2023, 1), (2023, 2)]);
+    }
+
+    #[test]
+    fn fetches_every_month() {
+        let (base_url, urls) = serve(0, 2);
+        let mut pages = fetch_months(&base_url, "KEY", NaiveDate::from_ymd(2023, 3, 1), NaiveDate::from_ymd(2023, 4, 30), &options()).unwrap();
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].body, FIXTURE.as_bytes());
+        let snapshot = pages[0].load(&Config::default(), &mut Diagnostics::new()).unwrap();
+        assert_eq!(snapshot.months["202303"][0].name, "Mathematik");
+        assert_eq!(*urls.lock().unwrap(), vec![
+            "/rapla?key=KEY&day=1&month=3&year=2023",
+            "/rapla?key=KEY&day=1&month=4&year=2023",
+        ]);
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let (base_url, urls) = serve(2, 3);
+        let pages = fetch_months(&base_url, "KEY", NaiveDate::from_ymd(2023, 3, 1), NaiveDate::from_ymd(2023, 3, 1), &options()).unwrap();
+
+        assert_eq!(pages.len(), 1);
+        assert_eq!(urls.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let (base_url, _) = serve(3, 3);
+        let result = fetch_months(&base_url, "KEY", NaiveDate::from_ymd(2023, 3, 1), NaiveDate::from_ymd(2023, 3, 1), &options());
+
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
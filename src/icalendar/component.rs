@@ -0,0 +1,374 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+
+/// The maximum length of a content line in octets, excluding the line break
+const MAX_LINE_OCTETS: usize = 75;
+const ICAL_DATETIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const ICAL_LOCAL_DATETIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// An iCalendar object, the `VCALENDAR` component.
+pub struct Calendar {
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+/// A component like `VEVENT`, consisting of properties and nested components.
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+/// A named property with optional parameters and a typed value.
+pub struct Property {
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub value: Value,
+}
+
+/// A property parameter like `CN`.
+pub struct Parameter {
+    pub name: String,
+    pub value: String,
+}
+
+/// The value types of RFC 5545, section 3.3, that are used by this program.
+pub enum Value {
+    /// A TEXT value, escaped on serialization
+    Text(String),
+    /// Multiple TEXT values, separated by commas
+    TextList(Vec<String>),
+    /// A DATE-TIME value in UTC
+    DateTime(DateTime<Utc>),
+    /// A DATE-TIME value in local time, usually qualified by a `TZID` parameter
+    LocalDateTime(NaiveDateTime),
+    /// A UTC-OFFSET value in seconds
+    UtcOffset(i32),
+    /// An INTEGER value
+    Integer(i64),
+    /// A CAL-ADDRESS value, which is a URI like `mailto:someone@example.com`
+    CalAddress(String),
+}
+
+impl Calendar {
+    pub fn new() -> Self {
+        Calendar { properties: Vec::new(), components: Vec::new() }
+    }
+
+    pub fn property(mut self, property: Property) -> Self {
+        self.properties.push(property);
+        self
+    }
+
+    pub fn component(mut self, component: Component) -> Self {
+        self.components.push(component);
+        self
+    }
+
+    pub fn write<W: io::Write>(&self, output: &mut W) -> io::Result<()> {
+        write_component(output, "VCALENDAR", &self.properties, &self.components)
+    }
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Component {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Component { name: name.into(), properties: Vec::new(), components: Vec::new() }
+    }
+
+    pub fn property(mut self, property: Property) -> Self {
+        self.properties.push(property);
+        self
+    }
+
+    /// Adds the property if it is present.
+    pub fn optional_property(self, property: Option<Property>) -> Self {
+        match property {
+            Some(property) => self.property(property),
+            None => self,
+        }
+    }
+
+    pub fn component(mut self, component: Component) -> Self {
+        self.components.push(component);
+        self
+    }
+
+    pub fn write<W: io::Write>(&self, output: &mut W) -> io::Result<()> {
+        write_component(output, &self.name, &self.properties, &self.components)
+    }
+}
+
+impl Property {
+    pub fn new<N: Into<String>>(name: N, value: Value) -> Self {
+        Property { name: name.into(), parameters: Vec::new(), value }
+    }
+
+    pub fn text<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        Self::new(name, Value::Text(value.into()))
+    }
+
+    pub fn date_time<N: Into<String>>(name: N, value: DateTime<Utc>) -> Self {
+        Self::new(name, Value::DateTime(value))
+    }
+
+    /// Creates a DATE-TIME property in the local time of the given time zone, referencing it by `TZID`.
+    pub fn local_date_time<N: Into<String>>(name: N, value: DateTime<Tz>) -> Self {
+        Self::new(name, Value::LocalDateTime(value.naive_local()))
+            .parameter("TZID", value.timezone().name())
+    }
+
+    pub fn parameter<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.parameters.push(Parameter { name: name.into(), value: value.into() });
+        self
+    }
+
+    /// Serializes this property to a single, unfolded content line.
+    pub fn to_line(&self) -> String {
+        let mut line = self.name.clone();
+        for parameter in &self.parameters {
+            line.push(';');
+            line.push_str(&parameter.name);
+            line.push('=');
+            // TZID values must not be quoted, see RFC 5545, section 3.2.19
+            if parameter.name == "TZID" {
+                line.push_str(&encode_param_value(&parameter.value));
+            } else {
+                line.push_str(&quote_param_value(&parameter.value));
+            }
+        }
+        line.push(':');
+        line.push_str(&self.value.to_string());
+        line
+    }
+
+    pub fn write<W: io::Write>(&self, output: &mut W) -> io::Result<()> {
+        write_ical_line(output, &self.to_line())
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Text(text) => f.write_str(&escape_text(text)),
+            Value::TextList(texts) => f.write_str(&texts.iter().map(|text| escape_text(text)).collect::<Vec<String>>().join(",")),
+            Value::DateTime(date_time) => write!(f, "{}", date_time.format(ICAL_DATETIME_FORMAT)),
+            Value::LocalDateTime(date_time) => write!(f, "{}", date_time.format(ICAL_LOCAL_DATETIME_FORMAT)),
+            Value::UtcOffset(offset) => {
+                let sign = if *offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                write!(f, "{}{:02}{:02}", sign, offset / 3600, offset / 60 % 60)?;
+                if offset % 60 != 0 {
+                    write!(f, "{:02}", offset % 60)?;
+                }
+                Ok(())
+            }
+            Value::Integer(integer) => write!(f, "{}", integer),
+            Value::CalAddress(address) => f.write_str(address),
+        }
+    }
+}
+
+fn write_component<W: io::Write>(output: &mut W, name: &str, properties: &[Property], components: &[Component]) -> io::Result<()> {
+    write_ical_line(output, &format!("BEGIN:{}", name))?;
+    for property in properties {
+        property.write(output)?;
+    }
+    for component in components {
+        component.write(output)?;
+    }
+    write_ical_line(output, &format!("END:{}", name))
+}
+
+/// Escapes a TEXT value according to RFC 5545, section 3.3.11.
+/// Control characters that can't be represented are dropped.
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                escaped.push_str("\\n");
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push('\t'),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quotes a parameter value according to RFC 5545, section 3.2, if it contains a colon, semicolon or comma.
+/// Double quotes and line breaks can't be part of parameter values, so they're encoded as described in RFC 6868.
+pub fn quote_param_value(value: &str) -> String {
+    let encoded = encode_param_value(value);
+    if value.contains([':', ';', ',']) {
+        format!("\"{}\"", encoded)
+    } else {
+        encoded
+    }
+}
+
+/// Encodes the characters of a parameter value that RFC 5545 doesn't allow, as described in RFC 6868.
+fn encode_param_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '^' => encoded.push_str("^^"),
+            '"' => encoded.push_str("^'"),
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                encoded.push_str("^n");
+            }
+            '\n' => encoded.push_str("^n"),
+            '\t' => encoded.push('\t'),
+            c if c.is_control() => {}
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Writes a content line, folding it according to RFC 5545, section 3.1.
+/// No physical line exceeds 75 octets (including the leading space of continuation lines)
+/// and multi-octet UTF-8 characters are never split.
+pub fn write_ical_line<W>(output: &mut W, line: &str) -> io::Result<()> where W: io::Write {
+    let mut line_rest = line;
+    let mut prefix = "";
+
+    loop {
+        let limit = MAX_LINE_OCTETS - prefix.len();
+        if line_rest.len() <= limit {
+            return write!(output, "{}{}\r\n", prefix, line_rest);
+        }
+
+        let mut split = limit;
+        while !line_rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        let parts = line_rest.split_at(split);
+        write!(output, "{}{}\r\n", prefix, parts.0)?;
+        line_rest = parts.1;
+        prefix = " ";
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn written_line(line: &str) -> String {
+        let mut output = Vec::new();
+        write_ical_line(&mut output, line).unwrap();
+        String::from_utf8(output).expect("Folding produced invalid UTF-8")
+    }
+
+    fn unfold(text: &str) -> String {
+        text.trim_end_matches("\r\n").replace("\r\n ", "")
+    }
+
+    #[test]
+    fn escapes_text_special_characters() {
+        assert_eq!(escape_text(r"a\b;c,d"), r"a\\b\;c\,d");
+        assert_eq!(escape_text("line\nnext\r\nlast\rend"), r"line\nnext\nlast\nend");
+        assert_eq!(escape_text("quote \" and colon: stay"), "quote \" and colon: stay");
+        assert_eq!(escape_text("bell\u{7}tab\t"), "belltab\t");
+        assert_eq!(escape_text(r"\n"), r"\\n");
+    }
+
+    #[test]
+    fn quotes_parameter_values() {
+        assert_eq!(quote_param_value("Mustermann, Max"), "\"Mustermann, Max\"");
+        assert_eq!(quote_param_value("Max \"The Prof\" Mustermann"), "Max ^'The Prof^' Mustermann");
+        assert_eq!(quote_param_value("a;b:c^d\ne"), "\"a;b:c^^d^ne\"");
+        assert_eq!(quote_param_value("Max Mustermann"), "Max Mustermann");
+    }
+
+    #[test]
+    fn never_quotes_time_zone_ids() {
+        let begin = Berlin.ymd(2023, 3, 2).and_hms(8, 0, 0);
+        assert_eq!(Property::local_date_time("DTSTART", begin).to_line(), "DTSTART;TZID=Europe/Berlin:20230302T080000");
+    }
+
+    #[test]
+    fn keeps_short_lines() {
+        let line = "SUMMARY:".to_string() + &"x".repeat(MAX_LINE_OCTETS - 8);
+        assert_eq!(written_line(&line), line.clone() + "\r\n");
+    }
+
+    #[test]
+    fn folds_at_75_octets() {
+        let line = "DESCRIPTION:".to_string() + &"x".repeat(300);
+        let written = written_line(&line);
+        let physical_lines: Vec<&str> = written.trim_end_matches("\r\n").split("\r\n").collect();
+
+        assert!(physical_lines.len() > 1);
+        assert_eq!(physical_lines[0].len(), MAX_LINE_OCTETS);
+        for physical_line in &physical_lines[1..] {
+            assert!(physical_line.starts_with(' '));
+            assert!(physical_line.len() <= MAX_LINE_OCTETS);
+        }
+        assert_eq!(unfold(&written), line);
+    }
+
+    #[test]
+    fn never_splits_umlauts() {
+        // Shift the umlauts across every possible fold position
+        for offset in 0..4 {
+            let line = "SUMMARY:".to_string() + &"x".repeat(offset) + &"äöü€ß".repeat(40);
+            let written = written_line(&line);
+            for physical_line in written.trim_end_matches("\r\n").split("\r\n") {
+                assert!(physical_line.len() <= MAX_LINE_OCTETS, "Line too long: {:?}", physical_line);
+            }
+            assert_eq!(unfold(&written), line);
+        }
+    }
+
+    #[test]
+    fn serializes_nasty_properties() {
+        let summary = Property::text("SUMMARY", "Klausur; \"Mathe\", Teil\\2\nRaum: A 1.23");
+        assert_eq!(summary.to_line(), "SUMMARY:Klausur\\; \"Mathe\"\\, Teil\\\\2\\nRaum: A 1.23");
+
+        let categories = Property::new("CATEGORIES", Value::TextList(vec!["a,b".to_string(), "c;d".to_string()]));
+        assert_eq!(categories.to_line(), "CATEGORIES:a\\,b,c\\;d");
+
+        let attendee = Property::new("ATTENDEE", Value::CalAddress("mailto:noreply@siphalor.de".to_string()))
+            .parameter("CN", "O\"Brien; Jürgen");
+        assert_eq!(attendee.to_line(), "ATTENDEE;CN=\"O^'Brien; Jürgen\":mailto:noreply@siphalor.de");
+    }
+
+    #[test]
+    fn writes_nested_components() {
+        let begin = Utc.ymd(2023, 3, 2).and_hms(7, 0, 0);
+        let calendar = Calendar::new()
+            .property(Property::text("VERSION", "2.0"))
+            .component(Component::new("VEVENT")
+                .property(Property::date_time("DTSTART", begin))
+                .optional_property(None)
+                .property(Property::text("SUMMARY", "Mathe")));
+
+        let mut output = Vec::new();
+        calendar.write(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nDTSTART:20230302T070000Z\r\nSUMMARY:Mathe\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        );
+    }
+}
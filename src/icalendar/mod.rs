@@ -0,0 +1,216 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use chrono::{Datelike, DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::config::Config;
+use crate::icalendar::component::{Calendar, Component, Property, Value};
+use crate::icalendar::timezone::timezone_component;
+use crate::model::{assign_uids, Event, EventData, Months};
+use crate::pipeline::EventSink;
+use crate::util::{write_atomically, Error};
+
+pub mod component;
+pub mod timezone;
+
+const NOREPLY_ADDRESS: &str = "mailto:noreply@siphalor.de";
+
+/// Writes the events as an iCalendar file.
+/// If a time zone is given, times are written in its local time and a matching `VTIMEZONE` is included,
+/// otherwise all times are written in UTC.
+pub fn write_calendar<W: io::Write>(
+    write: &mut W, events: &[&Event], time_zone: Option<Tz>, config: &Config,
+) -> io::Result<()> {
+    let now = Utc::now();
+    let mut calendar = Calendar::new()
+        .property(Property::text("VERSION", "2.0"))
+        .property(Property::text("PRODID", "-//Siphalor//DHiCalnigma//DE"))
+        .property(Property::text("X-ICALNIGMA-TIME", now.format("%d.%m.%Y %H:%M").to_string()));
+
+    if let Some(time_zone) = time_zone {
+        let years = events.iter().flat_map(|event| [event.begin.year(), event.end.year()]);
+        if let (Some(first_year), Some(last_year)) = (years.clone().min(), years.max()) {
+            calendar = calendar.component(timezone_component(time_zone, first_year..=last_year));
+        }
+    }
+
+    let uids = assign_uids(events);
+    for (event, uid) in events.iter().zip(uids) {
+        calendar = calendar.component(lecture_component(event, &uid, time_zone, now, config));
+    }
+    calendar.write(write)
+}
+
+/// An iCalendar file as part of a [`Pipeline`](crate::pipeline::Pipeline)
+///
+/// The file is replaced atomically, so that calendar clients never see a half-written file.
+pub struct CalendarFile {
+    /// The path of the file, or `-` for stdout
+    pub path: String,
+    pub time_zone: Option<Tz>,
+}
+
+impl CalendarFile {
+    pub fn new<P: Into<String>>(path: P, time_zone: Option<Tz>) -> Self {
+        CalendarFile { path: path.into(), time_zone }
+    }
+}
+
+impl EventSink for CalendarFile {
+    fn write(&mut self, months: &Months, config: &Config) -> Result<(), Error> {
+        let events: Vec<&Event> = months.values().flatten().collect();
+        let result = if self.path == "-" {
+            let mut output = io::stdout();
+            write_calendar(&mut output, &events, self.time_zone, config)
+                .and_then(|_| io::Write::flush(&mut output))
+        } else {
+            write_atomically(Path::new(&self.path), |mut output| {
+                write_calendar(&mut output, &events, self.time_zone, config)
+            })
+        };
+        result.map_err(|error| Error::Output(format!("{}: {}", self.path, error)))
+    }
+}
+
+/// Builds the `VEVENT` of an event.
+/// The stamp is used as `DTSTAMP` unless the archive tracked a modification time for the event.
+pub fn lecture_component(
+    event: &Event, uid: &str, time_zone: Option<Tz>, stamp: DateTime<Utc>, config: &Config,
+) -> Component {
+    let mut component = Component::new("VEVENT")
+        .property(Property::text("UID", uid))
+        .property(Property::date_time("DTSTAMP", event.last_modified.unwrap_or(stamp)))
+        .property(Property::new("SEQUENCE", Value::Integer(event.sequence as i64)))
+        .optional_property(event.last_modified.map(|last_modified| Property::date_time("LAST-MODIFIED", last_modified)))
+        .optional_property(event.creation.map(|creation| Property::date_time("CREATED", creation)))
+        .property(event_time_property("DTSTART", event.begin, time_zone))
+        .property(event_time_property("DTEND", event.end, time_zone))
+        .property(Property::text("SUMMARY", event.title()));
+
+    if !event.locations.is_empty() {
+        component = component.property(Property::text("LOCATION", event.locations.join(", ")));
+    }
+
+    let mut description = String::new();
+    let mut evt_categories: Vec<String> = Vec::new();
+
+    if let EventData::Lecture{categories, language, total_hours, ..} = &event.data {
+        evt_categories.push("LECTURE".to_string());
+
+        if !categories.is_empty() {
+            write!(description, "{}\n\n", categories.join(", ")).ok();
+            evt_categories.extend(categories.iter().cloned());
+        }
+
+        if let Some(language) = language {
+            writeln!(description, "Sprache: {}", language).ok();
+        }
+
+        if let Some(total_hours) = total_hours {
+            writeln!(description, "Insgesamte Stunden: {}", total_hours).ok();
+        }
+    } else {
+        match &event.data {
+            EventData::Exam => evt_categories.push("EXAM".to_string()),
+            EventData::Presentation => evt_categories.push("PRESENTATION".to_string()),
+            EventData::Holiday => evt_categories.push("HOLIDAY".to_string()),
+            EventData::Consultation => evt_categories.push("CONSULTATION".to_string()),
+            _ => {}
+        }
+    }
+
+    if config.is_online(&event.locations) {
+        evt_categories.push("ONLINE".to_string());
+    } else if !event.locations.is_empty() {
+        evt_categories.push("PRESENCE".to_string());
+    }
+
+    if !evt_categories.is_empty() {
+        component = component.property(Property::new("CATEGORIES", Value::TextList(evt_categories)));
+    }
+
+    if !event.lecturers.is_empty() {
+        component = component.property(address_property("ORGANIZER", &event.lecturers.first().unwrap().name));
+
+        writeln!(
+            description, "Dozent:innen: {}",
+            event.lecturers.iter().map(|l| l.name.as_str()).collect::<Vec<&str>>().join(", ")
+        ).ok();
+        for lecturer in &event.lecturers {
+            component = component.property(address_property("ATTENDEE", &lecturer.name));
+        }
+    } else {
+        description.push_str("Dozent:innen sind aufgrund von Datenschutzbedenken der DHBW nicht mehr öffentlich!")
+    }
+
+    for course in &event.courses {
+        component = component.property(address_property("ATTENDEE", course));
+    }
+
+    component.property(Property::text("DESCRIPTION", description))
+}
+
+fn event_time_property(name: &str, time: DateTime<Utc>, time_zone: Option<Tz>) -> Property {
+    match time_zone {
+        Some(time_zone) => Property::local_date_time(name, time.with_timezone(&time_zone)),
+        None => Property::date_time(name, time),
+    }
+}
+
+fn address_property(name: &str, common_name: &str) -> Property {
+    Property::new(name, Value::CalAddress(NOREPLY_ADDRESS.to_string()))
+        .parameter("CN", common_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::model::test_event;
+
+    use super::*;
+
+    fn lines(event: &Event, stamp: DateTime<Utc>) -> Vec<String> {
+        let mut output = Vec::new();
+        lecture_component(event, "uid@icalnigma", None, stamp, &Config::default()).write(&mut output).unwrap();
+        String::from_utf8(output).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn writes_revision_properties() {
+        let stamp = Utc.ymd(2023, 3, 10).and_hms(12, 0, 0);
+        let event = Event {
+            locations: vec!["A 1.23".to_string()],
+            sequence: 2,
+            last_modified: Some(Utc.ymd(2023, 3, 1).and_hms(9, 30, 0)),
+            data: EventData::Lecture {
+                number: None,
+                language: Some("Deutsch".to_string()),
+                kind: None,
+                categories: vec!["Informatik".to_string(), "Mathematik".to_string()],
+                total_hours: None,
+            },
+            ..test_event(Utc.ymd(2023, 3, 2).and_hms(7, 0, 0), Utc.ymd(2023, 3, 2).and_hms(9, 0, 0))
+        };
+
+        let lines = lines(&event, stamp);
+        assert!(lines.contains(&"DTSTAMP:20230301T093000Z".to_string()));
+        assert!(lines.contains(&"SEQUENCE:2".to_string()));
+        assert!(lines.contains(&"LAST-MODIFIED:20230301T093000Z".to_string()));
+        let categories: Vec<&String> = lines.iter().filter(|line| line.starts_with("CATEGORIES")).collect();
+        assert_eq!(categories, vec!["CATEGORIES:LECTURE,Informatik,Mathematik,PRESENCE"]);
+    }
+
+    #[test]
+    fn stamps_untracked_events_with_the_time_of_writing() {
+        let stamp = Utc.ymd(2023, 3, 10).and_hms(12, 0, 0);
+        let event = test_event(Utc.ymd(2023, 3, 2).and_hms(7, 0, 0), Utc.ymd(2023, 3, 2).and_hms(9, 0, 0));
+
+        let lines = lines(&event, stamp);
+        assert!(lines.contains(&"DTSTAMP:20230310T120000Z".to_string()));
+        assert!(lines.contains(&"SEQUENCE:0".to_string()));
+        assert!(!lines.iter().any(|line| line.starts_with("LAST-MODIFIED") || line.starts_with("CATEGORIES")));
+    }
+}
@@ -0,0 +1,138 @@
+use std::ops::RangeInclusive;
+
+use chrono::{DateTime, Duration, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+use crate::icalendar::component::{Component, Property, Value};
+
+/// The offset of a time zone at a certain point in time
+#[derive(PartialEq, Eq, Clone)]
+struct Observance {
+    offset: i32,
+    daylight: bool,
+    name: String,
+}
+
+/// Builds a `VTIMEZONE` component for the given time zone that covers all events in the given years.
+///
+/// chrono-tz doesn't expose its transition table, so the transitions are found by
+/// scanning the years day by day and narrowing each offset change down to the second.
+/// Every transition becomes its own `STANDARD` or `DAYLIGHT` observance without recurrence rules.
+pub fn timezone_component(time_zone: Tz, years: RangeInclusive<i32>) -> Component {
+    // Start a year early, so that the observance in effect at the beginning of the range is included
+    let start = Utc.ymd(years.start() - 1, 1, 1).and_hms(0, 0, 0);
+    let end = Utc.ymd(years.end() + 1, 1, 1).and_hms(0, 0, 0);
+
+    let mut component = Component::new("VTIMEZONE")
+        .property(Property::text("TZID", time_zone.name()));
+
+    let mut time = start;
+    let mut observance = observance_at(time_zone, time);
+    let mut found_transition = false;
+    while time < end {
+        let next_time = time + Duration::days(1);
+        let next_observance = observance_at(time_zone, next_time);
+        if next_observance != observance {
+            let transition = find_transition(time_zone, time, next_time);
+            component = component.component(observance_component(transition, &observance, &next_observance));
+            found_transition = true;
+        }
+        time = next_time;
+        observance = next_observance;
+    }
+
+    if !found_transition {
+        let observance = observance_at(time_zone, start);
+        component = component.component(observance_component(start, &observance, &observance));
+    }
+    component
+}
+
+fn observance_at(time_zone: Tz, time: DateTime<Utc>) -> Observance {
+    let offset = time_zone.offset_from_utc_datetime(&time.naive_utc());
+    Observance {
+        offset: offset.fix().local_minus_utc(),
+        daylight: offset.dst_offset() != Duration::zero(),
+        name: offset.abbreviation().to_string(),
+    }
+}
+
+/// Finds the first second in the given range at which the observance differs from the one at its start.
+fn find_transition(time_zone: Tz, mut before: DateTime<Utc>, mut after: DateTime<Utc>) -> DateTime<Utc> {
+    let observance = observance_at(time_zone, before);
+    while after - before > Duration::seconds(1) {
+        let middle = before + (after - before) / 2;
+        if observance_at(time_zone, middle) == observance {
+            before = middle;
+        } else {
+            after = middle;
+        }
+    }
+    after
+}
+
+fn observance_component(transition: DateTime<Utc>, from: &Observance, to: &Observance) -> Component {
+    // The onset of an observance is given in the local time that was in effect before it
+    let onset = transition.naive_utc() + Duration::seconds(from.offset as i64);
+    Component::new(if to.daylight { "DAYLIGHT" } else { "STANDARD" })
+        .property(Property::new("DTSTART", Value::LocalDateTime(onset)))
+        .property(Property::new("TZOFFSETFROM", Value::UtcOffset(from.offset)))
+        .property(Property::new("TZOFFSETTO", Value::UtcOffset(to.offset)))
+        .property(Property::text("TZNAME", to.name.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Europe::Berlin;
+
+    use crate::config::Config;
+    use crate::icalendar::write_calendar;
+    use crate::model::test_event;
+
+    use super::*;
+
+    #[test]
+    fn finds_berlin_transitions() {
+        let mut output = Vec::new();
+        timezone_component(Berlin, 2023..=2023).write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.starts_with("BEGIN:VTIMEZONE\r\nTZID:Europe/Berlin\r\n"));
+        assert!(output.contains(
+            "BEGIN:DAYLIGHT\r\nDTSTART:20230326T020000\r\nTZOFFSETFROM:+0100\r\nTZOFFSETTO:+0200\r\nTZNAME:CEST\r\nEND:DAYLIGHT\r\n"
+        ));
+        assert!(output.contains(
+            "BEGIN:STANDARD\r\nDTSTART:20231029T030000\r\nTZOFFSETFROM:+0200\r\nTZOFFSETTO:+0100\r\nTZNAME:CET\r\nEND:STANDARD\r\n"
+        ));
+    }
+
+    #[test]
+    fn covers_zones_without_transitions() {
+        let mut output = Vec::new();
+        timezone_component(Tz::UTC, 2023..=2023).write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("BEGIN:STANDARD\r\nDTSTART:20220101T000000\r\nTZOFFSETFROM:+0000\r\nTZOFFSETTO:+0000\r\n"));
+    }
+
+    #[test]
+    fn writes_events_in_local_time() {
+        let winter = test_event(Utc.ymd(2023, 3, 2).and_hms(7, 0, 0), Utc.ymd(2023, 3, 2).and_hms(11, 30, 0));
+        let summer = test_event(Utc.ymd(2023, 4, 6).and_hms(6, 0, 0), Utc.ymd(2023, 4, 6).and_hms(8, 0, 0));
+        let mut output = Vec::new();
+        write_calendar(&mut output, &[&winter, &summer], Some(Berlin), &Config::default()).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let times: Vec<&str> = output.lines()
+            .filter(|line| line.starts_with("DTSTART;") || line.starts_with("DTEND;"))
+            .collect();
+        assert_eq!(times, vec![
+            "DTSTART;TZID=Europe/Berlin:20230302T080000",
+            "DTEND;TZID=Europe/Berlin:20230302T123000",
+            "DTSTART;TZID=Europe/Berlin:20230406T080000",
+            "DTEND;TZID=Europe/Berlin:20230406T100000",
+        ]);
+        assert!(output.find("END:VTIMEZONE").unwrap() < output.find("BEGIN:VEVENT").unwrap());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use encoding_rs::Encoding;
+
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::model::Snapshot;
+use crate::pipeline::EventSource;
+use crate::rapla::load_events;
+use crate::util::Error;
+
+/// An input page together with the time it was generated at
+pub struct Input {
+    /// The path of the page, or `-` for stdin
+    pub path: String,
+    pub generated: DateTime<Utc>,
+    /// The encoding to read the page in, detected if not given
+    pub encoding: Option<&'static Encoding>,
+}
+
+/// Resolves the given input arguments to the pages they refer to, ordered from oldest to newest.
+///
+/// Arguments may be files, directories, whose HTML files are used, or glob patterns.
+/// The generation time of a page is the modification time of its file, stdin counts as just generated.
+/// Pages with the same generation time are ordered by their path, so that the order is deterministic.
+pub fn expand_inputs(arguments: &[String], encoding: Option<&'static Encoding>) -> Result<Vec<Input>, Error> {
+    let mut paths: Vec<String> = Vec::new();
+    for argument in arguments {
+        if argument == "-" || Path::new(argument).is_file() {
+            paths.push(argument.clone());
+        } else if Path::new(argument).is_dir() {
+            paths.extend(list_pages(argument)?);
+        } else if argument.contains(['*', '?', '[']) {
+            let mut matches = glob::glob(argument)
+                .map_err(|error| Error::Input(format!("Invalid pattern {}: {}", argument, error)))?
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<String>>();
+            if matches.is_empty() {
+                return Err(Error::Input(format!("No files match {}", argument)));
+            }
+            matches.sort();
+            paths.extend(matches);
+        } else {
+            return Err(Error::Input(format!("{}: No such file or directory", argument)));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut inputs = Vec::new();
+    for path in paths {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+
+        let generated = if path == "-" {
+            Utc::now()
+        } else {
+            fs::metadata(&path).and_then(|metadata| metadata.modified())
+                .map_err(|error| Error::Input(format!("{}: {}", path, error)))?
+                .into()
+        };
+        inputs.push(Input { path, generated, encoding });
+    }
+    inputs.sort_by(|a, b| a.generated.cmp(&b.generated).then_with(|| a.path.cmp(&b.path)));
+    Ok(inputs)
+}
+
+/// Lists the HTML files in a directory, sorted by name.
+fn list_pages(directory: &str) -> Result<Vec<String>, Error> {
+    let entries = fs::read_dir(directory)
+        .map_err(|error| Error::Input(format!("{}: {}", directory, error)))?;
+    let mut pages = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|error| Error::Input(format!("{}: {}", directory, error)))?.path();
+        let is_html = path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"));
+        if path.is_file() && is_html {
+            pages.push(path.to_string_lossy().into_owned());
+        }
+    }
+    if pages.is_empty() {
+        return Err(Error::Input(format!("{}: No HTML files in directory", directory)));
+    }
+    pages.sort();
+    Ok(pages)
+}
+
+impl EventSource for Input {
+    fn load(&mut self, config: &Config, diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+        let mut reader: Box<dyn Read> = if self.path == "-" {
+            Box::new(io::stdin())
+        } else {
+            Box::new(File::open(&self.path).map_err(|error| Error::Input(format!("{}: {}", self.path, error)))?)
+        };
+        load_page(&self.path, &mut reader, self.encoding, config, diagnostics)
+    }
+}
+
+/// Loads the events of a single page, recording problems with the name of the page.
+pub fn load_page<R: Read>(
+    name: &str, reader: &mut R, encoding: Option<&'static Encoding>, config: &Config, diagnostics: &mut Diagnostics,
+) -> Result<Snapshot, Error> {
+    let known_problems = diagnostics.problems.len();
+    let result = load_events(reader, encoding, config, diagnostics);
+    for problem in &mut diagnostics.problems[known_problems..] {
+        problem.location.input = Some(name.to_string());
+    }
+    result.map_err(|error| error.in_input(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    use crate::util::test_directory;
+
+    use super::*;
+
+    /// Creates a directory with empty pages that were generated the given number of seconds after some fixed time.
+    fn page_directory(name: &str, pages: &[(&str, u64)]) -> PathBuf {
+        let directory = test_directory(name);
+        for (page, seconds) in pages {
+            let file = File::create(directory.join(page)).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000 + seconds)).unwrap();
+        }
+        directory
+    }
+
+    fn names(inputs: &[Input]) -> Vec<String> {
+        inputs.iter()
+            .map(|input| Path::new(&input.path).file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn orders_pages_from_oldest_to_newest() {
+        let directory = page_directory("order", &[("a.html", 20), ("b.HTM", 10), ("c.html", 10), ("notes.txt", 0)]);
+
+        let inputs = expand_inputs(&[directory.to_string_lossy().into_owned()], None).unwrap();
+        assert_eq!(names(&inputs), vec!["b.HTM", "c.html", "a.html"]);
+        assert!(inputs[0].generated < inputs[2].generated);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn expands_patterns_and_skips_repeated_pages() {
+        let directory = page_directory("patterns", &[("week1.html", 10), ("week2.html", 0), ("month.html", 5)]);
+        let path = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        let inputs = expand_inputs(&[path("week*.html"), path("week1.html"), path("month.html")], None).unwrap();
+        assert_eq!(names(&inputs), vec!["week2.html", "month.html", "week1.html"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_missing_inputs() {
+        let directory = page_directory("missing", &[("notes.txt", 0)]);
+        let path = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        assert!(matches!(expand_inputs(&[path("page.html")], None), Err(Error::Input(_))));
+        assert!(matches!(expand_inputs(&[path("*.html")], None), Err(Error::Input(_))));
+        assert!(matches!(expand_inputs(&[path("")], None), Err(Error::Input(_))));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
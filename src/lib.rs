@@ -0,0 +1,83 @@
+//! Converts the HTML calendars of Rapla to iCalendar files.
+//!
+//! A conversion has three steps, which can also be used on their own:
+//!
+//! 1. [`load_events`] parses a Rapla page into a [`Snapshot`] of the [`Event`]s on the dates it covers (requires the `html` feature).
+//! 2. [`read_archive`], [`merge_archive`] and [`write_archive`] keep past events
+//!    that Rapla doesn't show anymore and track changes of the events.
+//! 3. [`write_calendar`] serializes the events as an iCalendar file.
+//!
+// The examples load Rapla pages, which needs the `html` feature
+#![cfg_attr(feature = "html", doc = "```no_run")]
+#![cfg_attr(not(feature = "html"), doc = "```ignore")]
+//! use std::fs::File;
+//!
+//! use chrono::Utc;
+//! use dh_icalnigma::{Config, Diagnostics, load_events, merge_archive, read_archive, write_archive, write_calendar};
+//! use dh_icalnigma::archive::ArchiveInfo;
+//!
+//! # fn main() -> Result<(), dh_icalnigma::Error> {
+//! let config = Config::default();
+//! let mut diagnostics = Diagnostics::new();
+//! let mut page = File::open("rapla.html").map_err(|error| dh_icalnigma::Error::Input(error.to_string()))?;
+//! let snapshot = load_events(&mut page, None, &config, &mut diagnostics)?;
+//!
+//! let now = Utc::now();
+//! let (info, archive_months) = read_archive("archive.json")?;
+//! let months = merge_archive(archive_months, snapshot, now, now);
+//! write_archive("archive.json", &ArchiveInfo { updated: now, ..info }, &months)?;
+//!
+//! let events: Vec<_> = months.values().flatten().collect();
+//! let mut output = File::create("calendar.ics").map_err(|error| dh_icalnigma::Error::Output(error.to_string()))?;
+//! write_calendar(&mut output, &events, None, &config).map_err(|error| dh_icalnigma::Error::Output(error.to_string()))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The same steps are also available as [`EventSource`]s and [`EventSink`]s, which a [`Pipeline`] connects:
+//!
+// The examples load Rapla pages, which needs the `html` feature
+#![cfg_attr(feature = "html", doc = "```no_run")]
+#![cfg_attr(not(feature = "html"), doc = "```ignore")]
+//! use dh_icalnigma::{Config, Diagnostics, Pipeline};
+//! use dh_icalnigma::archive::ArchiveFile;
+//! use dh_icalnigma::icalendar::CalendarFile;
+//! use dh_icalnigma::input::expand_inputs;
+//!
+//! # fn main() -> Result<(), dh_icalnigma::Error> {
+//! let mut pipeline = Pipeline::new()
+//!     .archive(ArchiveFile::new("archive.json"))
+//!     .sink(CalendarFile::new("calendar.ics", None));
+//! for input in expand_inputs(&["pages/".to_string()], None)? {
+//!     pipeline = pipeline.source(input);
+//! }
+//! pipeline.run(&Config::default(), &mut Diagnostics::new())
+//! # }
+//! ```
+//!
+//! The features `html` (parsing Rapla pages), `fetch` (downloading them) and `cli` (the command line interface)
+//! are enabled by default. Without them, only the model, the archive and the iCalendar writer are available.
+
+pub mod archive;
+pub mod config;
+pub mod diagnostics;
+#[cfg(feature = "fetch")]
+pub mod fetch;
+pub mod icalendar;
+#[cfg(feature = "html")]
+pub mod input;
+pub mod model;
+pub mod pipeline;
+#[cfg(feature = "html")]
+pub mod rapla;
+pub mod util;
+
+pub use crate::archive::{detect_changes, merge_archive, merge_events, read_archive, write_archive};
+pub use crate::config::Config;
+pub use crate::diagnostics::Diagnostics;
+pub use crate::icalendar::write_calendar;
+pub use crate::model::{DateRange, Event, EventData, Lecturer, Months, Snapshot};
+pub use crate::pipeline::{EventSink, EventSource, Pipeline};
+#[cfg(feature = "html")]
+pub use crate::rapla::load_events;
+pub use crate::util::Error;
@@ -1,27 +1,16 @@
-use std::io;
-use std::fs::{File, OpenOptions};
-use std::num::ParseIntError;
 use std::option::Option::Some;
+use std::process;
+use std::time::Duration;
 
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use chrono_tz::Europe::Berlin;
-use clap::Parser;
-use encoding_rs_io::DecodeReaderBytesBuilder;
-use html5ever::ParseOpts;
-use html5ever::tendril::TendrilSink;
-use lazy_static::lazy_static;
-use markup5ever_rcdom::{Handle, RcDom};
-use regex::Regex;
-use crate::archive::{read_archive, write_archive};
-
-use crate::icalendar::write_calendar;
-use crate::model::{Event, EventData, Months};
-use crate::util::{Day, Error, get_month_from_german, HandleExtensions, Month, Year};
-
-mod util;
-mod model;
-mod icalendar;
-mod archive;
+use clap::{AppSettings, Args, Parser, Subcommand};
+use encoding_rs::Encoding;
+use dh_icalnigma::{Config, Diagnostics, Error, Pipeline, read_archive};
+use dh_icalnigma::archive::{ArchiveFile, Change, ChangeQuery};
+use dh_icalnigma::fetch::{fetch_months, FetchOptions};
+use dh_icalnigma::icalendar::CalendarFile;
+use dh_icalnigma::input::expand_inputs;
 
 #[derive(Parser)]
 #[clap(
@@ -29,225 +18,203 @@ mod archive;
     author = "Siphalor <info@siphalor.de>",
     rename_all = "kebab",
     about = "An unofficial program that transpiles Rapla HTML sites to iCalendar files.",
+    after_help = "Exit codes: 3 = input not readable, 4 = input not parsable, 5 = archive failure, 6 = output not writable, 7 = invalid configuration",
+    setting = AppSettings::SubcommandsNegateReqs,
 )]
 struct Opts {
-    /// The HTML file to read in
-    #[clap(required=true)]
-    input: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
 
-    /// The output file
-    #[clap(required=true)]
-    output: String,
+    /// The HTML files to read in, directories or glob patterns of them, or - to read from stdin.
+    /// Events on dates that several files cover are taken from the newest of them.
+    /// The last path is the output file, or - to write to stdout
+    // A single positional, as clap skips a multi-value positional that follows options with values
+    #[clap(required=true, min_values=2, value_name="PATHS")]
+    paths: Vec<String>,
+
+    /// A JSON file with site specific rules, like how to tell courses from rooms
+    #[clap(short, long, global=true)]
+    config: Option<String>,
 
     /// Sets the archive file and enables archiving
-    #[clap(short, long)]
+    #[clap(short, long, global=true)]
     archive: Option<String>,
+
+    /// Writes times in local German time with a time zone definition instead of UTC
+    #[clap(long, global=true)]
+    local_time: bool,
+
+    /// Fails without touching the output and archive if any part of the input had to be skipped
+    #[clap(long, global=true)]
+    strict: bool,
+
+    /// The encoding of the input, like "utf-8" or "windows-1252", instead of detecting it
+    #[clap(long, global=true, parse(try_from_str = parse_encoding))]
+    encoding: Option<&'static Encoding>,
+
+    /// Archived events that began before this time are kept as they are, like "2023-03-01" or "2023-03-01 12:00".
+    /// Defaults to now
+    #[clap(long, global=true, parse(try_from_str = parse_cutoff))]
+    cutoff: Option<DateTime<Utc>>,
+
+    /// Writes the problems encountered while parsing to this file as JSON
+    #[clap(long, global=true)]
+    report: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Downloads the month views of a course calendar from Rapla instead of reading files
+    Fetch(FetchOpts),
+    /// Lists the changes of events recorded in the archive, see --archive
+    History(HistoryOpts),
+}
+
+#[derive(Args)]
+struct FetchOpts {
+    /// The URL of the Rapla calendar, like https://rapla.dhbw-stuttgart.de/rapla
+    url: String,
+
+    /// The key of the course calendar
+    key: String,
+
+    /// The output file, or - to write to stdout
+    output: String,
+
+    /// The first day to fetch, like 2023-03-01
+    #[clap(long, parse(try_from_str = parse_date))]
+    from: NaiveDate,
+
+    /// The last day to fetch, like 2023-08-31
+    #[clap(long, parse(try_from_str = parse_date))]
+    to: NaiveDate,
+
+    /// The timeout for each request in seconds
+    #[clap(long, default_value = "30")]
+    timeout: u64,
+
+    /// How often a failed request is repeated
+    #[clap(long, default_value = "3")]
+    retries: u32,
+
+    /// The wait between two requests in milliseconds
+    #[clap(long, default_value = "1000")]
+    delay: u64,
+}
+
+#[derive(Args)]
+struct HistoryOpts {
+    /// Only lists changes of events of this course
+    #[clap(long)]
+    course: Option<String>,
+
+    /// Only lists changes of events whose name contains this text
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Only lists changes of events on or after this day, like 2023-03-01
+    #[clap(long, parse(try_from_str = parse_date))]
+    from: Option<NaiveDate>,
+
+    /// Only lists changes of events on or before this day, like 2023-08-31
+    #[clap(long, parse(try_from_str = parse_date))]
+    to: Option<NaiveDate>,
 }
 
 fn main() {
     let opts: Opts = Opts::parse();
 
-    match File::open(opts.input) {
-        Ok(mut input_file) => {
-
-            match OpenOptions::new().read(false).write(true).truncate(true).create(true).open(opts.output) {
-                Ok(mut output_file) => {
-                    let res = load_events(&mut input_file);
-
-                    if let Err(error) = res {
-                        eprintln!("Failed to load events from file: {:?}", error);
-                        return;
-                    }
-
-                    let mut months = res.unwrap();
-
-                    if let Some(archive_path) = &opts.archive {
-                        match read_archive(archive_path) {
-                            Ok(mut archive_months) => {
-                                archive_months.extend(months);
-                                months = archive_months;
-                            }
-                            Err(error) => eprintln!("Failed to read archive: {}", error),
-                        }
-
-                        if let Err(error) = write_archive(archive_path, &months) {
-                            eprintln!("Failed to write archive: {}", error);
-                        }
-                    }
-
-                    write_calendar(&mut output_file, &months.into_values().flatten().collect());
-                }
-                Err(error) => {
-                    eprintln!("Failed to open output file: {}", error);
-                }
-            }
-        }
-        Err(error) => {
-            eprintln!("Failed to open input file: {}", error);
-        }
+    if let Err(error) = run(opts) {
+        eprintln!("{}", error);
+        process::exit(error.exit_code());
     }
 }
 
-fn load_events<R: io::Read>(input_stream: &mut R) -> Result<Months, util::Error> {
-    let mut input_stream = DecodeReaderBytesBuilder::new()
-        .encoding(Some(encoding_rs::WINDOWS_1252))
-        .build(input_stream);
-    let dom = html5ever::parse_document(RcDom::default(), ParseOpts {
-        ..Default::default()
-    })
-        .from_utf8()
-        .read_from(&mut input_stream)
-        .expect("Failed to parse input!");
-
-    let document = dom.document;
-    let html = document.get_node_by_tag_name("html").expect("Document does not have an html tag!");
-    let body = html.get_node_by_tag_name("body").expect("Document does not have a body tag!");
-
-    let mut months = Months::new();
-    for handle in body.get_nodes_by_tag_name("div") {
-        if let Some(val) = handle.get_attribute_value("class") {
-            if val == "calendar" {
-                if let Some((month, events)) = load_month(handle)? {
-                    months.insert(month, events);
-                }
-            }
-        }
+fn run(opts: Opts) -> Result<(), Error> {
+    if let Some(Command::History(history_opts)) = &opts.command {
+        return print_history(opts.archive.as_deref(), history_opts);
     }
 
-    Ok(months)
-}
-
-fn load_month(month_handle: Handle) -> Result<Option<(String, Vec<Event>)>, util::Error> {
-    let mut events = Vec::new();
-
-    if let Some(heading_handle) = month_handle.get_node_by_tag_name("h2") {
-        if let Some(heading_text) = heading_handle.get_content() {
-            let mut parts = heading_text.split_ascii_whitespace();
-            let month: Month = get_month_from_german(parts.next().ok_or("Invalid month heading text (empty)")?)?;
-            let year: Year = parts.next().ok_or("Invalid month heading text (year is missing!)")?
-                .parse().map_err(|err: ParseIntError| err.to_string())?;
-
-            if let Some(table_handle) = month_handle.get_node_by_tag_name("table") {
-                if let Some(tbody_handle) = table_handle.get_node_by_tag_name("tbody") {
-                    for row_handle in tbody_handle.get_nodes_by_tag_name("tr") {
-                        for cell_handle in row_handle.get_nodes_by_tag_name("td") {
-                            if cell_handle.get_attribute_value("class").map_or(true, |val| val != "month_cell") {
-                                continue;
-                            }
-
-                            match load_day(cell_handle, year, month) {
-                                Ok(day_events) => {
-                                    if let Some(day_events) = day_events {
-                                        events.extend(day_events)
-                                    }
-                                },
-                                Err(error) => eprintln!("Error in day: {:?}", error),
-                            }
-                        }
-                    }
-                }
+    let config = match &opts.config {
+        Some(config_path) => Config::load(config_path)?,
+        None => Config::default(),
+    };
+    let time_zone = if opts.local_time { Some(Berlin) } else { None };
+
+    let mut pipeline = Pipeline::new().strict(opts.strict).cutoff(opts.cutoff);
+    let (output_path, source) = match &opts.command {
+        Some(Command::Fetch(fetch_opts)) => {
+            let options = FetchOptions {
+                timeout: Duration::from_secs(fetch_opts.timeout),
+                retries: fetch_opts.retries,
+                backoff: Duration::from_secs(1),
+                delay: Duration::from_millis(fetch_opts.delay),
+                on_retry: |url, error, backoff| eprintln!("Failed to fetch {}: {}, retrying in {:?}", url, error, backoff),
+            };
+            for mut page in fetch_months(&fetch_opts.url, &fetch_opts.key, fetch_opts.from, fetch_opts.to, &options)? {
+                page.encoding = opts.encoding.or(page.encoding);
+                pipeline = pipeline.source(page);
             }
+            (fetch_opts.output.clone(), format!("{} (key {})", fetch_opts.url, fetch_opts.key))
         }
+        _ => {
+            let (output_path, inputs) = opts.paths.split_last().expect("clap requires an output path");
+            for input in expand_inputs(inputs, opts.encoding)? {
+                pipeline = pipeline.source(input);
+            }
+            (output_path.clone(), inputs.join(" "))
+        }
+    };
+    if let Some(archive_path) = &opts.archive {
+        pipeline = pipeline.archive(ArchiveFile::new(archive_path)
+            .metadata("source", source)
+            .on_lock_wait(|path| eprintln!("Waiting for another run to release {}", path.display())));
     }
+    pipeline = pipeline.sink(CalendarFile::new(output_path, time_zone));
 
-    if events.is_empty() {
-        return Ok(None)
+    let mut diagnostics = Diagnostics::new();
+    let result = pipeline.run(&config, &mut diagnostics);
+    diagnostics.print_summary();
+    if let Some(report_path) = &opts.report {
+        diagnostics.write_report(report_path)?;
     }
-    return Ok(Some(( events.first().unwrap().end.format("%Y%m").to_string(), events )))
+    result
 }
 
-fn load_day(cell_handle: Handle, year: Year, month: Month) -> Result<Option<Vec<Event>>, Error> {
-    let divs = cell_handle.get_nodes_by_tag_name("div");
-    if divs.len() < 2 { // The first div always contains the number of the day
-        return Ok(None);
+fn print_history(archive_path: Option<&str>, history_opts: &HistoryOpts) -> Result<(), Error> {
+    let archive_path = archive_path.ok_or_else(|| Error::Archive("The history requires an archive, see --archive".to_string()))?;
+    let (info, _) = read_archive(archive_path)?;
+
+    let local_midnight = |date: NaiveDate| Berlin.from_local_date(&date).unwrap().and_hms(0, 0, 0).with_timezone(&Utc);
+    let query = ChangeQuery {
+        course: history_opts.course.clone(),
+        name: history_opts.name.clone(),
+        from: history_opts.from.map(local_midnight),
+        to: history_opts.to.map(|to| local_midnight(to.succ())),
+    };
+    let changes: Vec<&Change> = info.changes.iter().filter(|change| query.matches(change)).collect();
+    if changes.is_empty() {
+        eprintln!("No matching changes");
     }
-
-    let mut divs = divs.into_iter();
-    let day: Day = divs.next().unwrap().get_content()
-        .ok_or("No day number found!")?
-        .parse().map_err(|err| format!("Failed to parse day number: {}", err))?;
-
-    let mut events = Vec::with_capacity(divs.len());
-    for div in divs {
-        if div.get_attribute_value("class").map_or(true, |val| val != "month_block") {
-            eprintln!("Skipping potential event, class={:?}, content={:?}", div.get_attribute_value("class"), div.get_content());
-            continue;
-        }
-
-        match process_event(div, year, month, day) {
-            Ok(event) => events.push(event),
-            Err(error) => eprintln!("Error in event: {:?}", error),
-        }
+    for change in changes {
+        println!("{}", change);
     }
-
-    Ok(Some(events))
+    Ok(())
 }
 
-fn process_event(event_handle: Handle, year: Year, month: Month, day: Day) -> Result<Event, Error> {
-    let link_handle = event_handle.get_node_by_tag_name("a").ok_or("No containing link in event!")?;
-    let mut title_lines = link_handle.get_text_nodes().into_iter();
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("Unknown encoding \"{}\"", label))
+}
 
-    if let Some(metadata_line) = title_lines.next() {
-        lazy_static!{
-            static ref TIME_PATTERN: Regex = Regex::new(r"^(\d{1,2}):(\d{1,2})\s*-\s*(\d{1,2}):(\d{1,2})").unwrap();
-        }
-        if let Some(captures) = TIME_PATTERN.captures(metadata_line.as_str()) {
-            let metadata_rest: &str = &metadata_line[captures.get(0).unwrap().end()..];
-            let date: chrono::Date<chrono_tz::Tz> = Berlin.ymd(year, month, day);
-            let begin = date.and_hms(
-                captures.get(1).unwrap().as_str().parse().unwrap(),
-                captures.get(2).unwrap().as_str().parse().unwrap(),
-                0
-            ).with_timezone(&Utc);
-            let end = date.and_hms(
-                captures.get(3).unwrap().as_str().parse().unwrap(),
-                captures.get(4).unwrap().as_str().parse().unwrap(),
-                0
-            ).with_timezone(&Utc);
-
-
-            let mut courses: Vec<String> = Vec::new();
-            let mut locations: Vec<String> = Vec::new();
-            for resource in metadata_rest.split(",") {
-                lazy_static! {
-                    static ref COURSE_PATTERN: Regex = Regex::new(r"^[A-Z]{3}-[A-Z0-9 ]+$").unwrap();
-                }
-                let trimmed = resource.trim();
-                if COURSE_PATTERN.is_match(trimmed) {
-                    courses.push(trimmed.to_string());
-                } else {
-                    locations.push(trimmed.to_string());
-                }
-            };
+fn parse_date(text: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|error| format!("Invalid date \"{}\": {}", text, error))
+}
 
-            let title = title_lines.next().unwrap_or_else(|| "missingno".to_string());
-            Ok(Event {
-                creation: None,
-                creator: None,
-                begin,
-                end,
-                data: if title.starts_with("Klausur ") { EventData::Exam } else {
-                    if locations.is_empty() {
-                        EventData::Other
-                    } else {
-                        EventData::Lecture {
-                            number: None,
-                            language: None,
-                            kind: None,
-                            categories: vec![],
-                            total_hours: None,
-                        }
-                    }
-                },
-                name: title,
-                lecturers: vec![],
-                locations,
-                courses,
-            })
-        } else {
-            Err("Failed to parse event metadata!".into())
-        }
-    } else {
-        Err("Encountered empty event!".into())
-    }
+/// Parses a local German date or date and time.
+fn parse_cutoff(text: &str) -> Result<DateTime<Utc>, String> {
+    let date_time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M")
+        .or_else(|_| parse_date(text).map(|date| date.and_hms(0, 0, 0)))?;
+    Berlin.from_local_datetime(&date_time).earliest()
+        .map(|date_time| date_time.with_timezone(&Utc))
+        .ok_or_else(|| format!("The time \"{}\" does not exist in Germany", text))
 }
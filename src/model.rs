@@ -1,25 +1,106 @@
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use chrono::{Datelike, DateTime, Utc};
+use chrono::{Datelike, DateTime, NaiveDate, Utc};
 
+use crate::util::local_date;
+
+/// Events grouped by their month, keyed like `202303`
 pub type Months = BTreeMap<String, Vec<Event>>;
 
+/// Events loaded from a source, together with the dates that the source covers.
+///
+/// Events of the covered dates that are missing from the source have been cancelled,
+/// while the source doesn't tell anything about the other dates.
+#[derive(Default)]
+pub struct Snapshot {
+    pub months: Months,
+    pub covered: Vec<DateRange>,
+}
+
+impl Snapshot {
+    /// Checks whether the snapshot covers the local date that the event begins on.
+    pub fn covers(&self, event: &Event) -> bool {
+        self.covered.iter().any(|range| range.contains(local_date(event.begin)))
+    }
+}
+
+impl From<Months> for Snapshot {
+    /// Takes the given months as complete, so that all of their days are covered.
+    fn from(months: Months) -> Self {
+        let covered = months.keys().filter_map(|month| DateRange::of_month(month)).collect();
+        Snapshot { months, covered }
+    }
+}
+
+/// A range of local dates, including its first and last date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+}
+
+impl DateRange {
+    /// The range of all days of a month, keyed like `202303`.
+    pub fn of_month(month: &str) -> Option<Self> {
+        let first = NaiveDate::parse_from_str(&format!("{}01", month), "%Y%m%d").ok()?;
+        let next_month = match first.month() {
+            12 => NaiveDate::from_ymd_opt(first.year() + 1, 1, 1)?,
+            month => NaiveDate::from_ymd_opt(first.year(), month + 1, 1)?,
+        };
+        Some(DateRange { first, last: next_month.pred() })
+    }
+
+    /// The smallest range that contains all of the given dates, if there are any.
+    pub fn spanning<I: IntoIterator<Item = NaiveDate>>(dates: I) -> Option<Self> {
+        dates.into_iter().fold(None, |range, date| match range {
+            Some(DateRange { first, last }) => Some(DateRange { first: first.min(date), last: last.max(date) }),
+            None => Some(DateRange { first: date, last: date }),
+        })
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.first <= date && date <= self.last
+    }
+}
+
+/// A single appointment of a Rapla reservation
 #[derive(Deserialize, Serialize)]
 pub struct Event {
     pub creation: Option<DateTime<Utc>>,
     pub creator: Option<String>,
+    /// When the event was last changed in Rapla
+    #[serde(default)]
+    pub last_changed: Option<DateTime<Utc>>,
     pub begin: DateTime<Utc>,
     pub end: DateTime<Utc>,
     pub name: String,
     pub lecturers: Vec<Lecturer>,
     pub locations: Vec<String>,
     pub courses: Vec<String>,
+    /// The id of the Rapla reservation that this event is an appointment of
+    #[serde(default)]
+    pub reservation: Option<String>,
+    /// The local date that the appointment was first seen on, if it has been rescheduled to another day since.
+    /// Identifies the appointment within its reservation, see [`Event::appointment_date`].
+    #[serde(default)]
+    pub original_date: Option<NaiveDate>,
+    /// The UID that this event was exported with before UIDs were derived from Rapla reservations.
+    /// Only set for events that had already been archived back then.
+    #[serde(default)]
+    pub legacy_uid: Option<String>,
+    /// The iCalendar revision of this event, incremented by the archive whenever the event changes
+    #[serde(default)]
+    pub sequence: u32,
+    /// When the archive first noticed this revision of the event
+    #[serde(default)]
+    pub last_modified: Option<DateTime<Utc>>,
     /// Additional event data
     pub data: EventData,
 }
 
+/// The type of an event with the data specific to it
 #[derive(Deserialize, Serialize)]
 pub enum EventData {
     Lecture {
@@ -35,6 +116,9 @@ pub enum EventData {
         total_hours: Option<u32>,
     },
     Exam,
+    Presentation,
+    Holiday,
+    Consultation,
     Other,
 }
 
@@ -44,7 +128,9 @@ pub struct Lecturer {
 }
 
 impl Event {
-    pub fn hash(&self) -> u64 {
+    /// The hash that was used as UID before UIDs were derived from Rapla reservations.
+    /// It is not stable across Rust releases and should only be used to migrate archived events.
+    pub fn legacy_hash(&self) -> u64 {
         #[derive(Hash, Debug)]
         struct EventHash<'a> {
             creation_time: i64,
@@ -66,15 +152,259 @@ impl Event {
             creator: self.creator.as_ref(),
         }.hash(&mut hasher);
 
-        return hasher.finish();
+        hasher.finish()
+    }
+
+    /// Identifies the appointment by its reservation, or its name if it has none, and its time.
+    pub fn key(&self) -> (String, DateTime<Utc>, DateTime<Utc>) {
+        let identity = match &self.reservation {
+            Some(reservation) => format!("reservation {}", reservation),
+            None => format!("name {}", self.name),
+        };
+        (identity, self.begin, self.end)
+    }
+
+    /// The date that identifies the appointment within its reservation:
+    /// the local date it was first seen on, which is usually the date it begins on.
+    pub fn appointment_date(&self) -> NaiveDate {
+        self.original_date.unwrap_or_else(|| local_date(self.begin))
     }
 
+    /// The summary of the event in calendars, including the kind of lectures
     pub fn title(&self) -> String {
-        if let EventData::Lecture{kind, ..} = &self.data {
-            if let Some(kind) = kind {
-                return format!("{} - {}", self.name, kind);
+        if let EventData::Lecture{kind: Some(kind), ..} = &self.data {
+            return format!("{} - {}", self.name, kind);
+        }
+        self.name.clone()
+    }
+}
+
+/// Determines the UIDs of the given events, in the same order.
+///
+/// Events of a Rapla reservation get the UID `rapla-<digest>-<date>@icalnigma`,
+/// where the digest is the [`stable_digest`] of the reservation id and the date is the [`Event::appointment_date`],
+/// so that the UID doesn't depend on which other appointments of the reservation are given.
+/// Further appointments of the reservation on the same date get the suffix `-<index>`, ordered by begin.
+/// Events without a reservation fall back to `event-<digest>@icalnigma` of their name and begin.
+/// Events with a legacy UID keep it.
+pub fn assign_uids(events: &[&Event]) -> Vec<String> {
+    let mut appointments: HashMap<(&str, NaiveDate), Vec<usize>> = HashMap::new();
+    for (index, event) in events.iter().enumerate() {
+        if let Some(reservation) = &event.reservation {
+            appointments.entry((reservation.as_str(), event.appointment_date())).or_default().push(index);
+        }
+    }
+
+    let mut same_day_indices = vec![0; events.len()];
+    for indices in appointments.values_mut() {
+        indices.sort_by_key(|index| (events[*index].begin, *index));
+        for (same_day_index, index) in indices.iter().enumerate() {
+            same_day_indices[*index] = same_day_index;
+        }
+    }
+
+    events.iter().zip(same_day_indices).map(|(event, same_day_index)| {
+        if let Some(legacy_uid) = &event.legacy_uid {
+            legacy_uid.clone()
+        } else if let Some(reservation) = &event.reservation {
+            let date = event.appointment_date().format("%Y%m%d");
+            match same_day_index {
+                0 => format!("rapla-{:016x}-{}@icalnigma", stable_digest(reservation), date),
+                _ => format!("rapla-{:016x}-{}-{}@icalnigma", stable_digest(reservation), date, same_day_index),
             }
+        } else {
+            format!("event-{:016x}@icalnigma", stable_digest(&format!("{}\n{}", event.name, event.begin.to_rfc3339())))
         }
-        return self.name.clone();
+    }).collect()
+}
+
+/// The 64 bit FNV-1a hash of the given text's UTF-8 bytes.
+/// Unlike Rust's default hasher, this is guaranteed to never change.
+pub fn stable_digest(text: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    text.bytes().fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+/// Merges the snapshots of several pages, which have to be ordered from oldest to newest.
+///
+/// Each page replaces the events of the dates it covers from older pages,
+/// so that events that were cancelled in the meantime are dropped. If the same event appears more than once in a month,
+/// only its first occurrence is kept. Events are the same if they have the same [`Event::key`].
+pub fn merge_pages(pages: Vec<Snapshot>) -> Snapshot {
+    let mut merged = Snapshot::default();
+    for page in pages {
+        for events in merged.months.values_mut() {
+            events.retain(|event| !page.covers(event));
+        }
+        for (month, events) in page.months {
+            merged.months.entry(month).or_default().extend(events);
+        }
+        merged.covered.extend(page.covered);
+    }
+
+    for events in merged.months.values_mut() {
+        let mut seen = HashSet::new();
+        events.retain(|event| seen.insert(event.key()));
+        sort_events(events);
+    }
+    merged
+}
+
+/// Sorts events by their time and name, so that merged months are ordered deterministically.
+pub fn sort_events(events: &mut [Event]) {
+    events.sort_by(|a, b| (a.begin, a.end, &a.name).cmp(&(b.begin, b.end, &b.name)));
+}
+
+/// An event named "Lecture" without any further details, for tests to fill in what they need with `..test_event(…)`.
+#[cfg(test)]
+pub(crate) fn test_event(begin: DateTime<Utc>, end: DateTime<Utc>) -> Event {
+    Event {
+        creation: None,
+        creator: None,
+        last_changed: None,
+        begin,
+        end,
+        name: "Lecture".to_string(),
+        lecturers: vec![],
+        locations: vec![],
+        courses: vec![],
+        reservation: None,
+        original_date: None,
+        legacy_uid: None,
+        sequence: 0,
+        last_modified: None,
+        data: EventData::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn event(reservation: Option<&str>, month: u32, day: u32, hour: u32) -> Event {
+        let begin = Utc.ymd(2023, month, day).and_hms(hour, 0, 0);
+        Event {
+            reservation: reservation.map(str::to_string),
+            ..test_event(begin, begin + Duration::hours(1))
+        }
+    }
+
+    fn uids(events: &[Event]) -> Vec<String> {
+        assign_uids(&events.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn digests_like_fnv_1a() {
+        assert_eq!(stable_digest(""), 0xcbf29ce484222325);
+        assert_eq!(stable_digest("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(stable_digest("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn derives_uids_from_appointment_dates() {
+        let digest = stable_digest("1");
+        let month = uids(&[event(Some("1"), 3, 2, 8), event(Some("1"), 3, 9, 8), event(Some("1"), 4, 6, 8)]);
+        let week = uids(&[event(Some("1"), 4, 6, 8)]);
+
+        assert_eq!(month[2], format!("rapla-{:016x}-20230406@icalnigma", digest));
+        assert_eq!(week[0], month[2]);
+    }
+
+    #[test]
+    fn uses_local_appointment_dates() {
+        let uids = uids(&[event(Some("1"), 3, 1, 23)]);
+        assert_eq!(uids[0], format!("rapla-{:016x}-20230302@icalnigma", stable_digest("1")));
+    }
+
+    #[test]
+    fn keeps_original_dates_of_rescheduled_appointments() {
+        let mut rescheduled = event(Some("1"), 3, 10, 8);
+        rescheduled.original_date = Some(NaiveDate::from_ymd(2023, 3, 9));
+        assert_eq!(uids(&[rescheduled]), uids(&[event(Some("1"), 3, 9, 8)]));
+    }
+
+    #[test]
+    fn numbers_appointments_on_the_same_date() {
+        let digest = stable_digest("1");
+        let uids = uids(&[event(Some("1"), 3, 2, 12), event(Some("1"), 3, 2, 8), event(Some("2"), 3, 2, 8)]);
+        assert_eq!(uids, vec![
+            format!("rapla-{:016x}-20230302-1@icalnigma", digest),
+            format!("rapla-{:016x}-20230302@icalnigma", digest),
+            format!("rapla-{:016x}-20230302@icalnigma", stable_digest("2")),
+        ]);
+    }
+
+    fn page(events: Vec<Event>) -> Snapshot {
+        let mut months = Months::new();
+        for event in events {
+            months.entry(event.begin.format("%Y%m").to_string()).or_default().push(event);
+        }
+        Snapshot::from(months)
+    }
+
+    fn days(months: &Months) -> Vec<(String, u32)> {
+        months.iter()
+            .flat_map(|(month, events)| events.iter().map(move |event| (month.clone(), event.begin.day())))
+            .collect()
+    }
+
+    #[test]
+    fn merges_pages_by_month() {
+        let old = page(vec![event(Some("1"), 3, 2, 8), event(Some("1"), 3, 9, 8), event(Some("1"), 4, 6, 8)]);
+        let new = page(vec![event(Some("1"), 3, 16, 8), event(Some("1"), 3, 9, 8)]);
+
+        let merged = merge_pages(vec![old, new]);
+        assert_eq!(days(&merged.months), vec![("202303".to_string(), 9), ("202303".to_string(), 16), ("202304".to_string(), 6)]);
+        assert_eq!(merged.covered.len(), 3);
+    }
+
+    #[test]
+    fn merges_partial_pages_by_date() {
+        let month = page(vec![event(Some("1"), 3, 2, 8), event(Some("1"), 3, 9, 8), event(Some("1"), 3, 16, 8)]);
+        let mut week = page(vec![event(Some("1"), 3, 10, 8)]);
+        week.covered = vec![DateRange { first: NaiveDate::from_ymd(2023, 3, 6), last: NaiveDate::from_ymd(2023, 3, 12) }];
+
+        let merged = merge_pages(vec![month, week]);
+        assert_eq!(days(&merged.months), vec![("202303".to_string(), 2), ("202303".to_string(), 10), ("202303".to_string(), 16)]);
+    }
+
+    #[test]
+    fn covers_whole_months() {
+        let range = |month: &str| DateRange::of_month(month).map(|range| (range.first.to_string(), range.last.to_string()));
+        assert_eq!(range("202302"), Some(("2023-02-01".to_string(), "2023-02-28".to_string())));
+        assert_eq!(range("202312"), Some(("2023-12-01".to_string(), "2023-12-31".to_string())));
+        assert_eq!(range("2023"), None);
+
+        let snapshot = Snapshot::from(page(vec![event(Some("1"), 3, 2, 8)]).months);
+        assert!(snapshot.covers(&event(None, 3, 31, 21)));
+        assert!(!snapshot.covers(&event(None, 3, 31, 22)));
+    }
+
+    #[test]
+    fn drops_duplicate_events_of_a_page() {
+        let mut renamed = event(Some("1"), 3, 2, 8);
+        renamed.name = "Renamed".to_string();
+        let events = vec![event(Some("1"), 3, 9, 8), event(Some("1"), 3, 2, 8), renamed, event(Some("2"), 3, 2, 8)];
+
+        let merged = merge_pages(vec![page(events)]);
+        let names: Vec<(u32, &str, Option<&str>)> = merged.months["202303"].iter()
+            .map(|event| (event.begin.day(), event.name.as_str(), event.reservation.as_deref()))
+            .collect();
+        assert_eq!(names, vec![(2, "Lecture", Some("1")), (2, "Lecture", Some("2")), (9, "Lecture", Some("1"))]);
+    }
+
+    #[test]
+    fn prefers_legacy_uids() {
+        let mut legacy = event(Some("1"), 3, 2, 8);
+        legacy.legacy_uid = Some("legacy@icalnigma".to_string());
+        let other = event(None, 3, 2, 8);
+
+        let uids = uids(&[legacy, other]);
+        assert_eq!(uids[0], "legacy@icalnigma");
+        assert_eq!(uids[1], format!("event-{:016x}@icalnigma", stable_digest("Lecture\n2023-03-02T08:00:00+00:00")));
     }
 }
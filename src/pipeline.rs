@@ -0,0 +1,217 @@
+use chrono::{DateTime, Utc};
+
+use crate::archive::merge_archive;
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::model::{merge_pages, Months, Snapshot};
+use crate::util::Error;
+
+/// Something that events can be loaded from, like a Rapla page
+pub trait EventSource {
+    /// Loads the events and the dates they cover, recording problems that could be worked around in the diagnostics.
+    fn load(&mut self, config: &Config, diagnostics: &mut Diagnostics) -> Result<Snapshot, Error>;
+}
+
+/// Something that events can be written to, like an iCalendar file
+pub trait EventSink {
+    fn write(&mut self, months: &Months, config: &Config) -> Result<(), Error>;
+}
+
+/// Something that events can be written to and loaded back from, like the archive
+pub trait EventStore: EventSource + EventSink {}
+
+impl<T: EventSource + EventSink> EventStore for T {}
+
+/// Loads events from sources, merges them with an archive and writes them to sinks.
+#[derive(Default)]
+pub struct Pipeline {
+    sources: Vec<Box<dyn EventSource>>,
+    archive: Option<Box<dyn EventStore>>,
+    sinks: Vec<Box<dyn EventSink>>,
+    strict: bool,
+    cutoff: Option<DateTime<Utc>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a source. Sources have to be added from oldest to newest, see [`merge_pages`].
+    pub fn source<S: EventSource + 'static>(mut self, source: S) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Sets the archive that the loaded events are merged into with [`merge_archive`].
+    /// It is written before any of the sinks.
+    pub fn archive<S: EventStore + 'static>(mut self, archive: S) -> Self {
+        self.archive = Some(Box::new(archive));
+        self
+    }
+
+    /// Sets the time before which archived events are frozen, see [`merge_events`](crate::archive::merge_events).
+    /// Defaults to the time the pipeline is run at.
+    pub fn cutoff(mut self, cutoff: Option<DateTime<Utc>>) -> Self {
+        self.cutoff = cutoff;
+        self
+    }
+
+    pub fn sink<S: EventSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Makes the pipeline fail before writing anything if any source had to skip parts of its input.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Runs the pipeline.
+    ///
+    /// Nothing is written if the archive can't be loaded, as the sinks would lose the archived events.
+    /// Otherwise every sink is written even if the archive or a previous sink fails,
+    /// the first error is returned afterwards.
+    pub fn run(mut self, config: &Config, diagnostics: &mut Diagnostics) -> Result<(), Error> {
+        let mut pages = Vec::with_capacity(self.sources.len());
+        for source in &mut self.sources {
+            pages.push(source.load(config, diagnostics)?);
+        }
+        let snapshot = merge_pages(pages);
+        diagnostics.record_months(&snapshot.months);
+
+        if self.strict && !diagnostics.is_empty() {
+            return Err(format!("Skipped {} parts of the input in strict mode", diagnostics.problems.len()).into());
+        }
+
+        let mut result = Ok(());
+        let months = match &mut self.archive {
+            Some(archive) => {
+                let archived = archive.load(config, diagnostics)?;
+                let now = Utc::now();
+                let months = merge_archive(archived.months, snapshot, now, self.cutoff.unwrap_or(now));
+                result = archive.write(&months, config);
+                months
+            }
+            None => snapshot.months,
+        };
+
+        for sink in &mut self.sinks {
+            let sink_result = sink.write(&months, config);
+            if result.is_ok() {
+                result = sink_result;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::diagnostics::DiagnosticKind;
+    use crate::util::Location;
+
+    use super::*;
+
+    struct Page;
+
+    struct SkippingPage;
+
+    impl EventSource for SkippingPage {
+        fn load(&mut self, _config: &Config, diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+            diagnostics.push(DiagnosticKind::UnparseableEvent, "Failed to parse event metadata!".to_string(), Location::default());
+            Ok(Snapshot::default())
+        }
+    }
+
+    impl EventSource for Page {
+        fn load(&mut self, _config: &Config, _diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+            Ok(Snapshot::default())
+        }
+    }
+
+    struct BrokenArchive;
+
+    impl EventSource for BrokenArchive {
+        fn load(&mut self, _config: &Config, _diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+            Err(Error::Archive("Failed to parse archive".to_string()))
+        }
+    }
+
+    impl EventSink for BrokenArchive {
+        fn write(&mut self, _months: &Months, _config: &Config) -> Result<(), Error> {
+            panic!("The unreadable archive was written");
+        }
+    }
+
+    struct Archive(Rc<Cell<bool>>);
+
+    impl EventSource for Archive {
+        fn load(&mut self, _config: &Config, _diagnostics: &mut Diagnostics) -> Result<Snapshot, Error> {
+            Ok(Snapshot::default())
+        }
+    }
+
+    impl EventSink for Archive {
+        fn write(&mut self, _months: &Months, _config: &Config) -> Result<(), Error> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    struct Calendar(Rc<Cell<bool>>);
+
+    impl EventSink for Calendar {
+        fn write(&mut self, _months: &Months, _config: &Config) -> Result<(), Error> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_nothing_if_the_archive_is_unreadable() {
+        let written = Rc::new(Cell::new(false));
+        let result = Pipeline::new()
+            .source(Page)
+            .archive(BrokenArchive)
+            .sink(Calendar(written.clone()))
+            .run(&Config::default(), &mut Diagnostics::new());
+
+        assert!(matches!(result, Err(Error::Archive(_))));
+        assert!(!written.get());
+    }
+
+    #[test]
+    fn writes_every_sink() {
+        let written = Rc::new(Cell::new(false));
+        Pipeline::new()
+            .source(Page)
+            .sink(Calendar(written.clone()))
+            .run(&Config::default(), &mut Diagnostics::new())
+            .unwrap();
+
+        assert!(written.get());
+    }
+
+    #[test]
+    fn writes_nothing_if_strict_and_parts_were_skipped() {
+        let archived = Rc::new(Cell::new(false));
+        let written = Rc::new(Cell::new(false));
+        let mut diagnostics = Diagnostics::new();
+        let result = Pipeline::new()
+            .source(SkippingPage)
+            .archive(Archive(archived.clone()))
+            .sink(Calendar(written.clone()))
+            .strict(true)
+            .run(&Config::default(), &mut diagnostics);
+
+        assert!(matches!(result, Err(Error::Parse(_, _))));
+        assert_eq!(diagnostics.problems.len(), 1);
+        assert!(!archived.get());
+        assert!(!written.get());
+    }
+}
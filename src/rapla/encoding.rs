@@ -0,0 +1,83 @@
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+/// The number of bytes that are searched for an encoding declaration, like browsers do
+const PRESCAN_LENGTH: usize = 1024;
+
+/// Determines the encoding of an HTML page.
+///
+/// A byte order mark takes precedence over a `<meta>` charset declaration, which takes precedence over an XML prolog.
+/// Without any of them the page is assumed to be UTF-8 if it is valid UTF-8, otherwise Windows-1252 like older Rapla exports.
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    let head = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+    if let Some(encoding) = find_meta_charset(head).or_else(|| find_xml_encoding(head)) {
+        // A page that could be read to find the declaration can't be UTF-16
+        if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+            return encoding_rs::UTF_8;
+        }
+        return encoding;
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        encoding_rs::UTF_8
+    } else {
+        encoding_rs::WINDOWS_1252
+    }
+}
+
+/// Finds `<meta charset="...">` or `<meta http-equiv="Content-Type" content="...; charset=...">`.
+fn find_meta_charset(head: &[u8]) -> Option<&'static Encoding> {
+    lazy_static! {
+        static ref META_PATTERN: Regex = Regex::new(r"(?i-u)<meta\s[^>]*>").unwrap();
+        static ref CHARSET_PATTERN: Regex = Regex::new(r#"(?i-u)charset\s*=\s*["']?\s*([^"'\s;/>]+)"#).unwrap();
+    }
+
+    META_PATTERN.find_iter(head)
+        .filter_map(|meta| CHARSET_PATTERN.captures(meta.as_bytes()))
+        .find_map(|captures| Encoding::for_label(&captures[1]))
+}
+
+/// Finds the encoding in an XML prolog like `<?xml version="1.0" encoding="...">`.
+fn find_xml_encoding(head: &[u8]) -> Option<&'static Encoding> {
+    lazy_static! {
+        static ref PROLOG_PATTERN: Regex = Regex::new(r#"(?-u)^\s*<\?xml\s[^>]*encoding\s*=\s*["']([^"']+)["']"#).unwrap();
+    }
+
+    PROLOG_PATTERN.captures(head).and_then(|captures| Encoding::for_label(&captures[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_byte_order_mark() {
+        let page = b"\xEF\xBB\xBF<html><head><meta charset=\"windows-1252\"></head></html>";
+        assert_eq!(detect_encoding(page), encoding_rs::UTF_8);
+    }
+
+    #[test]
+    fn reads_meta_declarations() {
+        assert_eq!(detect_encoding(b"<html><head><meta charset=utf-8>"), encoding_rs::UTF_8);
+        assert_eq!(
+            detect_encoding(b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=ISO-8859-1\">M\xE4rz"),
+            encoding_rs::WINDOWS_1252
+        );
+    }
+
+    #[test]
+    fn reads_xml_prolog() {
+        assert_eq!(detect_encoding(b"<?xml version=\"1.0\" encoding=\"ISO-8859-15\"?><html>"), encoding_rs::ISO_8859_15);
+    }
+
+    #[test]
+    fn guesses_undeclared_encodings() {
+        assert_eq!(detect_encoding("<h2>März 2023</h2>".as_bytes()), encoding_rs::UTF_8);
+        assert_eq!(detect_encoding(b"<h2>M\xE4rz 2023</h2>"), encoding_rs::WINDOWS_1252);
+    }
+}
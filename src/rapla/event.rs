@@ -0,0 +1,150 @@
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use lazy_static::lazy_static;
+use markup5ever_rcdom::Handle;
+use regex::Regex;
+
+use crate::config::{Config, EventKind, ResourceKind};
+use crate::model::{Event, EventData, Lecturer};
+use crate::rapla::tooltip::{parse_tooltip, Tooltip};
+use crate::util::{Day, Error, HandleExtensions, Month, Year};
+
+pub fn process_event(event_handle: Handle, year: Year, month: Month, day: Day, config: &Config) -> Result<Event, Error> {
+    let link_handle = event_handle.get_node_by_tag_name("a")
+        .ok_or_else(|| Error::from("No containing link in event!").in_event(&event_handle.get_text()))?;
+    let event_text = link_handle.get_text_nodes().join(" ");
+    read_event(&event_handle, &link_handle, year, month, day, config).map_err(|error| error.in_event(&event_text))
+}
+
+fn read_event(
+    event_handle: &Handle, link_handle: &Handle, year: Year, month: Month, day: Day, config: &Config,
+) -> Result<Event, Error> {
+    let mut title_lines = link_handle.get_text_nodes().into_iter();
+
+    if let Some(metadata_line) = title_lines.next() {
+        lazy_static!{
+            static ref TIME_PATTERN: Regex = Regex::new(r"^(\d{1,2}):(\d{1,2})\s*-\s*(\d{1,2}):(\d{1,2})").unwrap();
+        }
+        if let Some(captures) = TIME_PATTERN.captures(metadata_line.as_str()) {
+            let metadata_rest: &str = &metadata_line[captures[0].len()..];
+            let date = NaiveDate::from_ymd_opt(year, month, day)
+                .ok_or_else(|| format!("Invalid date {}.{}.{}", day, month, year))?;
+            let begin = berlin_time(date, &captures[1], &captures[2])?;
+            let end = berlin_time(date, &captures[3], &captures[4])?;
+
+            let tooltip = parse_tooltip(event_handle).unwrap_or_default();
+
+            // The week view lists resources in separate spans instead of the metadata line
+            let resource_spans: Vec<String> = link_handle.get_nodes_by_tag_name("span").into_iter()
+                .filter(|span| span.get_attribute_value("class").as_deref() == Some("resource"))
+                .map(|span| span.get_text().trim().to_string())
+                .collect();
+
+            let resources = if !resource_spans.is_empty() {
+                resource_spans
+            } else {
+                metadata_rest.split(',').map(|resource| resource.trim().to_string()).collect()
+            };
+
+            let reservation = link_handle.get_attribute_value("href").and_then(|href| parse_reservation_id(&href));
+            Ok(build_event(begin, end, tooltip, title_lines.next(), resources, reservation, config))
+        } else {
+            Err("Failed to parse event metadata!".into())
+        }
+    } else {
+        Err("Encountered empty event!".into())
+    }
+}
+
+/// Converts a time on the given day in Berlin to UTC.
+/// Times that don't exist because of the switch to daylight saving time are rejected.
+fn berlin_time(date: NaiveDate, hour: &str, minute: &str) -> Result<DateTime<Utc>, Error> {
+    let hour = hour.parse().map_err(|_| format!("Invalid hour \"{}\"", hour))?;
+    let minute = minute.parse().map_err(|_| format!("Invalid minute \"{}\"", minute))?;
+    let date_time = date.and_hms_opt(hour, minute, 0)
+        .ok_or_else(|| format!("Invalid time {:02}:{:02}", hour, minute))?;
+    Berlin.from_local_datetime(&date_time).earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| format!("The time {} does not exist in Berlin", date_time).into())
+}
+
+/// Creates an event from the details in a tooltip.
+/// The title and resources visible outside of the tooltip are only used if the tooltip lacks them.
+pub fn build_event(
+    begin: DateTime<Utc>, end: DateTime<Utc>, tooltip: Tooltip, title: Option<String>, resources: Vec<String>,
+    reservation: Option<String>, config: &Config,
+) -> Event {
+    let resources = if tooltip.resources.is_empty() { resources } else { tooltip.resources };
+    let title = tooltip.name
+        .or(title)
+        .unwrap_or_else(|| "missingno".to_string());
+    let kind = config.classify_event(&title, tooltip.kind.as_deref(), &resources);
+
+    let mut lecturers = tooltip.lecturers;
+    let mut courses: Vec<String> = Vec::new();
+    let mut locations: Vec<String> = Vec::new();
+    for resource in resources.into_iter().filter(|resource| !resource.is_empty()) {
+        match config.classify_resource(&resource) {
+            ResourceKind::Course => courses.push(resource),
+            ResourceKind::Room | ResourceKind::Online => locations.push(resource),
+            ResourceKind::Lecturer => {
+                if !lecturers.iter().any(|lecturer| lecturer.name == resource) {
+                    lecturers.push(Lecturer { name: resource });
+                }
+            }
+        }
+    }
+
+    let kind = kind.unwrap_or(if locations.is_empty() { EventKind::Other } else { EventKind::Lecture });
+    Event {
+        creation: tooltip.created,
+        creator: tooltip.creator,
+        last_changed: tooltip.last_changed,
+        begin,
+        end,
+        data: match kind {
+            EventKind::Lecture => EventData::Lecture {
+                number: tooltip.number,
+                language: tooltip.language,
+                kind: tooltip.kind,
+                categories: tooltip.categories,
+                total_hours: tooltip.total_hours,
+            },
+            EventKind::Exam => EventData::Exam,
+            EventKind::Presentation => EventData::Presentation,
+            EventKind::Holiday => EventData::Holiday,
+            EventKind::Consultation => EventData::Consultation,
+            EventKind::Other => EventData::Other,
+        },
+        name: title,
+        lecturers,
+        locations,
+        courses,
+        reservation,
+        original_date: None,
+        legacy_uid: None,
+        sequence: 0,
+        last_modified: None,
+    }
+}
+
+/// Extracts the reservation id from the link of an event.
+/// Known query parameters are preferred, otherwise the whole link identifies the reservation.
+pub fn parse_reservation_id(href: &str) -> Option<String> {
+    let href = href.trim();
+    if href.is_empty() || href.starts_with('#') || href.starts_with("javascript:") {
+        return None;
+    }
+
+    if let Some((_, query)) = href.split_once('?') {
+        let query = query.split('#').next().unwrap_or_default();
+        for parameter in query.split('&') {
+            if let Some((key, value)) = parameter.split_once('=') {
+                if matches!(key, "id" | "reservation" | "reservation_id" | "event") && !value.is_empty() {
+                    return Some(value.to_string());
+                }
+            }
+        }
+    }
+    Some(href.to_string())
+}
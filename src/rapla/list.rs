@@ -0,0 +1,161 @@
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use lazy_static::lazy_static;
+use markup5ever_rcdom::Handle;
+use regex::Regex;
+
+use crate::config::Config;
+use crate::diagnostics::{DiagnosticKind, Diagnostics};
+use crate::model::Event;
+use crate::rapla::event::{build_event, parse_reservation_id};
+use crate::rapla::get_table_rows;
+use crate::rapla::tooltip::{get_cell_values, Tooltip};
+use crate::util::{Error, HandleExtensions, parse_german_datetime};
+
+const BEGIN_LABELS: [&str; 4] = ["beginn", "begin", "start", "von"];
+const END_LABELS: [&str; 3] = ["ende", "end", "bis"];
+
+/// Loads all events from a Rapla reservation list.
+///
+/// Each row is a single appointment. The columns are identified by their header labels,
+/// which mostly match the labels of the tooltips in the calendar views.
+pub fn load_list(table_handle: Handle, config: &Config, diagnostics: &mut Diagnostics) -> Result<Vec<Event>, Error> {
+    let mut rows = get_table_rows(&table_handle).into_iter();
+    let labels = rows.next().map(|row| get_header_labels(&row)).unwrap_or_default();
+
+    let begin_column = labels.iter().position(|label| BEGIN_LABELS.contains(&label.as_str()))
+        .ok_or("Reservation list has no begin column!")?;
+    let end_column = labels.iter().position(|label| END_LABELS.contains(&label.as_str()))
+        .ok_or("Reservation list has no end column!")?;
+
+    let mut events = Vec::new();
+    for row_handle in rows {
+        let cells = row_handle.get_nodes_by_tag_name("td");
+        if cells.is_empty() {
+            continue;
+        }
+
+        match process_row(&labels, &cells, begin_column, end_column, config) {
+            Ok(event) => events.push(event),
+            Err(error) => diagnostics.record(DiagnosticKind::UnparseableEvent, error.in_event(&row_handle.get_text())),
+        }
+    }
+
+    Ok(events)
+}
+
+/// Checks whether the given table looks like a Rapla reservation list.
+pub fn is_list_table(table_handle: &Handle) -> bool {
+    get_table_rows(table_handle).first().is_some_and(|row| {
+        let labels = get_header_labels(row);
+        labels.iter().any(|label| BEGIN_LABELS.contains(&label.as_str()))
+            && labels.iter().any(|label| END_LABELS.contains(&label.as_str()))
+    })
+}
+
+fn get_header_labels(row_handle: &Handle) -> Vec<String> {
+    row_handle.children.borrow().iter()
+        .filter(|handle| handle.is_tag("th") || handle.is_tag("td"))
+        .map(|handle| handle.get_text().trim().trim_end_matches(':').trim_end().to_lowercase())
+        .collect()
+}
+
+fn process_row(
+    labels: &[String], cells: &[Handle], begin_column: usize, end_column: usize, config: &Config,
+) -> Result<Event, Error> {
+    let cell_text = |column: usize| cells.get(column)
+        .map(|cell| get_cell_values(cell).join(" "))
+        .unwrap_or_default();
+
+    let begin_text = cell_text(begin_column);
+    let begin = parse_german_datetime(&begin_text)
+        .ok_or_else(|| format!("Invalid begin \"{}\"", begin_text))?;
+    let end_text = cell_text(end_column);
+    let end = parse_german_datetime(&end_text)
+        .or_else(|| parse_time_on_day(&end_text, begin))
+        .ok_or_else(|| format!("Invalid end \"{}\"", end_text))?;
+
+    let mut tooltip = Tooltip::default();
+    for (column, (label, cell)) in labels.iter().zip(cells).enumerate() {
+        if column != begin_column && column != end_column {
+            tooltip.apply_row(label, get_cell_values(cell));
+        }
+    }
+
+    let reservation = cells.iter()
+        .find_map(|cell| cell.find_descendant(|handle| handle.is_tag("a")))
+        .and_then(|link| link.get_attribute_value("href"))
+        .and_then(|href| parse_reservation_id(&href));
+    Ok(build_event(begin, end, tooltip, None, vec![], reservation, config))
+}
+
+/// Parses a bare time like `12:30` on the same Berlin day as the given reference time.
+fn parse_time_on_day(text: &str, reference: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    lazy_static! {
+        static ref TIME_PATTERN: Regex = Regex::new(r"(\d{1,2}):(\d{2})").unwrap();
+    }
+    let captures = TIME_PATTERN.captures(text)?;
+    let time = NaiveTime::from_hms_opt(captures[1].parse().ok()?, captures[2].parse().ok()?, 0)?;
+    let date_time = reference.with_timezone(&Berlin).naive_local().date().and_time(time);
+    Berlin.from_local_datetime(&date_time).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::EventData;
+    use crate::rapla::parse_body;
+
+    use super::*;
+
+    const LIST: &str = r#"<table class="eventtable"><thead><tr><th>Name</th><th>Art</th><th>Beginn:</th><th>Ende</th><th>Ressourcen</th><th>Personen</th></tr></thead>
+<tbody>
+<tr><td><a href="/rapla?page=reservation&amp;id=17">Mathe</a></td><td>Vorlesung</td><td>Mo 06.03.23 08:00</td><td>12:00</td><td>TIN-21B3, A 1.23</td><td>Mustermann, Max</td></tr>
+<tr><td>Klausur Physik</td><td>Klausur</td><td>14.04.2023 09:00</td><td>14.04.2023 11:00</td><td>TIN-21B3</td><td></td></tr>
+<tr><td>Kaputt</td><td></td><td>bald</td><td>später</td><td></td><td></td></tr>
+</tbody></table>"#;
+
+    fn is_list(html: &str) -> bool {
+        let (_dom, body) = parse_body(html);
+        is_list_table(&body.find_descendant(|handle| handle.is_tag("table")).unwrap())
+    }
+
+    #[test]
+    fn loads_list_rows() {
+        let (_dom, body) = parse_body(LIST);
+        let table_handle = body.find_descendant(|handle| handle.is_tag("table")).unwrap();
+        let mut diagnostics = Diagnostics::new();
+        let events = load_list(table_handle, &Config::default(), &mut diagnostics).unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "Mathe");
+        assert_eq!(events[0].begin, Utc.ymd(2023, 3, 6).and_hms(7, 0, 0));
+        assert_eq!(events[0].end, Utc.ymd(2023, 3, 6).and_hms(11, 0, 0));
+        assert_eq!(events[0].courses, vec!["TIN-21B3"]);
+        assert_eq!(events[0].locations, vec!["A 1.23"]);
+        assert_eq!(events[0].lecturers[0].name, "Mustermann, Max");
+        assert_eq!(events[0].reservation.as_deref(), Some("17"));
+        assert!(matches!(events[1].data, EventData::Exam));
+        assert_eq!(events[1].end, Utc.ymd(2023, 4, 14).and_hms(9, 0, 0));
+
+        assert_eq!(diagnostics.problems.len(), 1);
+        assert_eq!(diagnostics.problems[0].kind, DiagnosticKind::UnparseableEvent);
+    }
+
+    #[test]
+    fn requires_begin_and_end_columns() {
+        assert!(is_list(LIST));
+        assert!(is_list("<table><tr><th>Name</th><th>Von</th><th>Bis</th></tr></table>"));
+        assert!(!is_list("<table><tr><th>Name</th><th>Beginn</th></tr></table>"));
+        assert!(!is_list("<table></table>"));
+    }
+
+    #[test]
+    fn parses_end_times_on_the_day_of_the_begin() {
+        // 00:30 in Berlin is still the previous day in UTC
+        let begin = Utc.ymd(2023, 3, 5).and_hms(23, 30, 0);
+        assert_eq!(parse_time_on_day("bis 02:00", begin), Some(Utc.ymd(2023, 3, 6).and_hms(1, 0, 0)));
+        assert_eq!(parse_time_on_day("12:00", Utc.ymd(2023, 7, 3).and_hms(6, 0, 0)), Some(Utc.ymd(2023, 7, 3).and_hms(10, 0, 0)));
+        assert_eq!(parse_time_on_day("25:00", begin), None);
+        assert_eq!(parse_time_on_day("später", begin), None);
+    }
+}
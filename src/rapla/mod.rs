@@ -0,0 +1,227 @@
+use std::io;
+use std::option::Option::Some;
+
+use encoding_rs::Encoding;
+use html5ever::ParseOpts;
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::{Handle, RcDom};
+
+use crate::config::Config;
+use crate::diagnostics::Diagnostics;
+use crate::model::{DateRange, Event, Months, Snapshot};
+use crate::rapla::encoding::detect_encoding;
+use crate::rapla::list::{is_list_table, load_list};
+use crate::rapla::month::load_month;
+use crate::rapla::week::{find_week_year, load_week};
+use crate::util::{Error, HandleExtensions, local_date};
+
+mod encoding;
+mod event;
+mod list;
+mod month;
+mod tooltip;
+mod week;
+
+/// The different page layouts that Rapla can render a calendar in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Month,
+    Week,
+    List,
+}
+
+/// Loads all events from a Rapla HTML page, together with the dates that the page covers.
+/// The encoding of the page is detected unless one is given.
+/// Problems that only affect single days or events are recorded in the diagnostics and skipped.
+pub fn load_events<R: io::Read>(
+    input_stream: &mut R, encoding: Option<&'static Encoding>, config: &Config, diagnostics: &mut Diagnostics,
+) -> Result<Snapshot, Error> {
+    let mut bytes = Vec::new();
+    input_stream.read_to_end(&mut bytes).map_err(|error| Error::Input(error.to_string()))?;
+    let encoding = encoding.unwrap_or_else(|| detect_encoding(&bytes));
+    let (text, _) = encoding.decode_with_bom_removal(&bytes);
+
+    let dom = html5ever::parse_document(RcDom::default(), ParseOpts {
+        ..Default::default()
+    })
+        .one(text.as_ref());
+
+    let document = dom.document;
+    let html = document.get_node_by_tag_name("html").ok_or("Document does not have an html tag!")?;
+    let body = html.get_node_by_tag_name("body").ok_or("Document does not have a body tag!")?;
+
+    let mut snapshot = Snapshot::default();
+    // Pages without any calendar, like Rapla's login page, mustn't be mistaken for an empty calendar
+    match detect_layout(&body).ok_or("No Rapla calendar found")? {
+        Layout::Month => {
+            for handle in find_calendars(&body) {
+                if let Some((month, events)) = load_month(handle, config, diagnostics)? {
+                    snapshot.covered.extend(DateRange::of_month(&month));
+                    snapshot.months.insert(month, events);
+                }
+            }
+        }
+        Layout::Week => {
+            let year = find_week_year(&body).ok_or("Failed to determine the year of the week view!")?;
+            for table_handle in find_tables(&body, &is_week_table) {
+                let (events, covered) = load_week(table_handle, year, config, diagnostics)?;
+                snapshot.covered.extend(covered);
+                insert_by_month(&mut snapshot.months, events);
+            }
+        }
+        Layout::List => {
+            // The list doesn't tell which dates it was filtered to, so it covers the dates of its appointments
+            let mut events = Vec::new();
+            for table_handle in find_tables(&body, &is_list_table) {
+                events.extend(load_list(table_handle, config, diagnostics)?);
+            }
+            snapshot.covered.extend(DateRange::spanning(events.iter().map(|event| local_date(event.begin))));
+            insert_by_month(&mut snapshot.months, events);
+        }
+    }
+
+    Ok(snapshot)
+}
+
+/// Determines the layout of the given page by looking for Rapla's characteristic table classes and month calendars.
+/// Returns `None` if the page doesn't contain any calendar.
+pub fn detect_layout(body: &Handle) -> Option<Layout> {
+    if !find_tables(body, &is_week_table).is_empty() {
+        Some(Layout::Week)
+    } else if !find_tables(body, &is_list_table).is_empty() {
+        Some(Layout::List)
+    } else if !find_calendars(body).is_empty() {
+        Some(Layout::Month)
+    } else {
+        None
+    }
+}
+
+/// Finds the month calendars of a month view.
+fn find_calendars(body: &Handle) -> Vec<Handle> {
+    body.get_nodes_by_tag_name("div").into_iter()
+        .filter(|handle| handle.get_attribute_value("class").as_deref() == Some("calendar"))
+        .collect()
+}
+
+fn is_week_table(table_handle: &Handle) -> bool {
+    table_handle.get_attribute_value("class").as_deref() == Some("week_table")
+}
+
+/// Finds all tables below the given node that satisfy the predicate.
+fn find_tables(handle: &Handle, predicate: &dyn Fn(&Handle) -> bool) -> Vec<Handle> {
+    let mut tables = Vec::new();
+    for child in handle.children.borrow().iter() {
+        if child.is_tag("table") && predicate(child) {
+            tables.push(child.clone());
+        } else {
+            tables.extend(find_tables(child, predicate));
+        }
+    }
+    tables
+}
+
+/// Returns the rows of a table, including those inside of `thead` and `tbody` sections.
+fn get_table_rows(table_handle: &Handle) -> Vec<Handle> {
+    let mut rows = table_handle.get_nodes_by_tag_name("tr");
+    for section in ["thead", "tbody"] {
+        for section_handle in table_handle.get_nodes_by_tag_name(section) {
+            rows.extend(section_handle.get_nodes_by_tag_name("tr"));
+        }
+    }
+    rows
+}
+
+/// Sorts the given events into the months of their local begin dates, as views like the week view may span multiple months.
+/// This files them like the month view does, which shows events on the day they begin.
+fn insert_by_month(months: &mut Months, events: Vec<Event>) {
+    for event in events {
+        months.entry(local_date(event.begin).format("%Y%m").to_string()).or_default().push(event);
+    }
+}
+
+/// Parses an HTML snippet and returns its body, for testing the parsers of the different layouts.
+/// The DOM has to be kept alive as long as the body is used, as dropping it clears the tree.
+#[cfg(test)]
+fn parse_body(html: &str) -> (RcDom, Handle) {
+    let dom = html5ever::parse_document(RcDom::default(), ParseOpts::default()).one(html);
+    let html = dom.document.get_node_by_tag_name("html").unwrap();
+    let body = html.get_node_by_tag_name("body").unwrap();
+    (dom, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn layout(html: &str) -> Option<Layout> {
+        let (_dom, body) = parse_body(html);
+        detect_layout(&body)
+    }
+
+    fn load(html: &str) -> Snapshot {
+        let snapshot = load_events(&mut html.as_bytes(), None, &Config::default(), &mut Diagnostics::new()).unwrap();
+        for events in snapshot.months.values() {
+            assert!(events.iter().all(|event| event.reservation.is_some()));
+        }
+        snapshot
+    }
+
+    fn months(snapshot: &Snapshot) -> Vec<(&str, usize)> {
+        snapshot.months.iter().map(|(month, events)| (month.as_str(), events.len())).collect()
+    }
+
+    fn range(first: (i32, u32, u32), last: (i32, u32, u32)) -> DateRange {
+        DateRange { first: NaiveDate::from_ymd(first.0, first.1, first.2), last: NaiveDate::from_ymd(last.0, last.1, last.2) }
+    }
+
+    #[test]
+    fn covers_the_months_of_month_views() {
+        let snapshot = load(r#"<div class="calendar"><h2>Februar 2023</h2><table><tbody><tr><td class="month_cell"><div>1</div></td></tr></tbody></table></div>
+<div class="calendar"><h2>März 2023</h2><table><tbody><tr><td class="month_cell"><div>1</div><div class="month_block"><a href="x?id=1">08:00 -10:00<br>Mathe</a></div></td></tr></tbody></table></div>"#);
+
+        assert_eq!(months(&snapshot), vec![("202302", 0), ("202303", 1)]);
+        assert_eq!(snapshot.covered, vec![range((2023, 2, 1), (2023, 2, 28)), range((2023, 3, 1), (2023, 3, 31))]);
+    }
+
+    #[test]
+    fn files_week_events_by_their_local_begin() {
+        let snapshot = load(r#"<form><input type="hidden" name="year" value="2023"></form><table class="week_table"><tbody>
+<tr><td class="week_number">KW 9</td><td class="week_header">Di 28.02.</td><td class="week_header">Mi 01.03.</td></tr>
+<tr><th class="week_times">00:00</th><td class="week_block"><a href="x?id=1">08:00&#160;-10:00<br>Mathe</a></td><td class="week_block"><a href="x?id=2">00:15&#160;-00:45<br>Nachtschicht</a></td></tr>
+</tbody></table>"#);
+
+        // The second event begins on 28.02. in UTC, but on 01.03. in Berlin
+        assert_eq!(months(&snapshot), vec![("202302", 1), ("202303", 1)]);
+        assert_eq!(snapshot.covered, vec![range((2023, 2, 28), (2023, 3, 1))]);
+    }
+
+    #[test]
+    fn covers_the_dates_of_list_appointments() {
+        let snapshot = load(r#"<table class="eventtable"><thead><tr><th>Name</th><th>Beginn</th><th>Ende</th></tr></thead><tbody>
+<tr><td><a href="x?id=1">Mathe</a></td><td>28.02.2023 08:00</td><td>10:00</td></tr>
+<tr><td><a href="x?id=2">Physik</a></td><td>01.04.2023 00:30</td><td>01:30</td></tr>
+</tbody></table>"#);
+
+        assert_eq!(months(&snapshot), vec![("202302", 1), ("202304", 1)]);
+        assert_eq!(snapshot.covered, vec![range((2023, 2, 28), (2023, 4, 1))]);
+    }
+
+    #[test]
+    fn detects_layouts() {
+        assert_eq!(layout(r#"<div class="calendar"><h2>März 2023</h2><table><tbody><tr><td class="month_cell"></td></tr></tbody></table></div>"#), Some(Layout::Month));
+        assert_eq!(layout(r#"<div><table class="week_table"><tr><td class="week_header">Mo 06.03.</td></tr></table></div>"#), Some(Layout::Week));
+        assert_eq!(layout(r#"<table class="eventtable"><thead><tr><th>Name</th><th>Beginn:</th><th>Ende</th></tr></thead></table>"#), Some(Layout::List));
+        // A table that only has a begin column is no reservation list
+        assert_eq!(layout(r#"<table><tr><th>Beginn</th><th>Name</th></tr></table>"#), None);
+    }
+
+    #[test]
+    fn rejects_pages_without_calendars() {
+        let login = r#"<form action="rapla?page=login" method="post"><input name="username"><input type="password" name="password"></form>"#;
+        let result = load_events(&mut login.as_bytes(), None, &Config::default(), &mut Diagnostics::new());
+        assert!(matches!(result, Err(Error::Parse(message, _)) if message == "No Rapla calendar found"));
+    }
+}
@@ -0,0 +1,226 @@
+use std::num::ParseIntError;
+
+use markup5ever_rcdom::Handle;
+
+use crate::config::Config;
+use crate::diagnostics::{DiagnosticKind, Diagnostics};
+use crate::model::Event;
+use crate::rapla::event::process_event;
+use crate::util::{Day, Error, get_month_from_german, HandleExtensions, Location, Month, Year};
+
+/// Loads the events of a month calendar, keyed by the month in its heading.
+/// Months without events are returned as well, so that they can be told apart from months that weren't loaded.
+/// Calendars without a heading are skipped and recorded in the diagnostics.
+pub fn load_month(month_handle: Handle, config: &Config, diagnostics: &mut Diagnostics) -> Result<Option<(String, Vec<Event>)>, Error> {
+    let mut events = Vec::new();
+
+    let heading_text = match month_handle.get_node_by_tag_name("h2").and_then(|heading_handle| heading_handle.get_content()) {
+        Some(heading_text) => heading_text,
+        None => {
+            diagnostics.push(DiagnosticKind::SkippedCalendar, "Skipping month calendar without heading".to_string(), Location::default());
+            return Ok(None);
+        },
+    };
+    let (month, year) = parse_heading(&heading_text).map_err(|error| error.in_month(&heading_text))?;
+
+    // A month without a table would be taken as a month without events, so the table must be there
+    let tbody_handle = month_handle.get_node_by_tag_name("table")
+        .and_then(|table_handle| table_handle.get_node_by_tag_name("tbody"))
+        .ok_or_else(|| Error::from("Month calendar has no table!").in_month(&heading_text))?;
+    for row_handle in tbody_handle.get_nodes_by_tag_name("tr") {
+        for cell_handle in row_handle.get_nodes_by_tag_name("td") {
+            if cell_handle.get_attribute_value("class").as_deref() != Some("month_cell") {
+                continue;
+            }
+
+            match load_day(cell_handle, &heading_text, year, month, config, diagnostics) {
+                Ok(day_events) => {
+                    if let Some(day_events) = day_events {
+                        events.extend(day_events)
+                    }
+                },
+                Err(error) => diagnostics.record(DiagnosticKind::SkippedCell, error.in_month(&heading_text)),
+            }
+        }
+    }
+
+    Ok(Some((format!("{:04}{:02}", year, month), events)))
+}
+
+fn parse_heading(heading_text: &str) -> Result<(Month, Year), Error> {
+    let mut parts = heading_text.split_ascii_whitespace();
+    let month: Month = get_month_from_german(parts.next().ok_or("Invalid month heading text (empty)")?)?;
+    let year: Year = parts.next().ok_or("Invalid month heading text (year is missing!)")?
+        .parse().map_err(|err: ParseIntError| err.to_string())?;
+    Ok((month, year))
+}
+
+fn load_day(
+    cell_handle: Handle, heading_text: &str, year: Year, month: Month, config: &Config, diagnostics: &mut Diagnostics,
+) -> Result<Option<Vec<Event>>, Error> {
+    let divs = cell_handle.get_nodes_by_tag_name("div");
+    if divs.len() < 2 { // The first div always contains the number of the day
+        return Ok(None);
+    }
+
+    let mut divs = divs.into_iter();
+    let day_handle = divs.next().ok_or("No day number found!")?;
+    let day_text = day_handle.get_content().ok_or("No day number found!")?;
+    let day: Day = day_text.trim()
+        .parse().map_err(|err| Error::from(format!("Failed to parse day number: {}", err)).on_day(&day_text))?;
+
+    let mut events = Vec::with_capacity(divs.len());
+    for div in divs {
+        if div.get_attribute_value("class").as_deref() != Some("month_block") {
+            diagnostics.push(
+                DiagnosticKind::UnknownClass,
+                format!("Skipping potential event with class {:?}", div.get_attribute_value("class").unwrap_or_default()),
+                Location {
+                    input: None,
+                    month: Some(heading_text.to_string()),
+                    day: Some(day_text.trim().to_string()),
+                    event: Some(div.get_text().split_whitespace().collect::<Vec<&str>>().join(" ")),
+                },
+            );
+            continue;
+        }
+
+        match process_event(div, year, month, day, config) {
+            Ok(event) => events.push(event),
+            Err(error) => diagnostics.record(DiagnosticKind::UnparseableEvent, error.on_day(&day_text).in_month(heading_text)),
+        }
+    }
+
+    Ok(Some(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Value};
+
+    use crate::diagnostics::Diagnostic;
+    use crate::model::Months;
+    use crate::rapla::parse_body;
+    use crate::util::test_directory;
+
+    use super::*;
+
+    const SKIPPED_PARTS: &str = r#"<td class="month_cell"><div>7</div><div class="month_note">Feiertag</div><div class="month_block"><a href="x?id=1">08:00 -10:00<br>Mathe</a></div></td>
+<td class="month_cell"><div>8.</div><div class="month_block"><a href="x?id=2">08:00 -10:00<br>Physik</a></div></td>"#;
+
+    fn load_with_diagnostics(heading: &str, cells: &str) -> (Vec<Event>, Diagnostics) {
+        let html = format!(r#"<div class="calendar"><h2>{}</h2><table><tbody><tr>{}</tr></tbody></table></div>"#, heading, cells);
+        let (_dom, body) = parse_body(&html);
+        let calendar = body.find_descendant(|handle| handle.is_tag("div")).unwrap();
+        let mut diagnostics = Diagnostics::new();
+        let (_, events) = load_month(calendar, &Config::default(), &mut diagnostics).unwrap().unwrap();
+        (events, diagnostics)
+    }
+
+    fn load(heading: &str, cells: &str) -> (Vec<Event>, Vec<Diagnostic>) {
+        let (events, diagnostics) = load_with_diagnostics(heading, cells);
+        (events, diagnostics.problems)
+    }
+
+    /// Loads a single event block on the given day, which has to be skipped, and returns the recorded problem.
+    fn unparseable(heading: &str, day: &str, block: &str) -> Diagnostic {
+        let (events, mut problems) = load(heading, &format!(r#"<td class="month_cell"><div>{}</div>{}</td>"#, day, block));
+        assert!(events.is_empty());
+        assert_eq!(problems.len(), 1);
+        problems.remove(0)
+    }
+
+    fn located(problem: &Diagnostic) -> (DiagnosticKind, Option<&str>, Option<&str>, Option<&str>) {
+        let location = &problem.location;
+        (problem.kind, location.month.as_deref(), location.day.as_deref(), location.event.as_deref())
+    }
+
+    #[test]
+    fn rejects_times_in_the_daylight_saving_gap() {
+        let problem = unparseable("März 2023", "26", r#"<div class="month_block"><a href="x?id=1">02:30 -03:30<br>Mathe</a></div>"#);
+        assert_eq!(located(&problem), (DiagnosticKind::UnparseableEvent, Some("März 2023"), Some("26"), Some("02:30 -03:30 Mathe")));
+        assert_eq!(problem.message, "The time 2023-03-26 02:30:00 does not exist in Berlin");
+    }
+
+    #[test]
+    fn rejects_impossible_dates() {
+        let problem = unparseable("April 2023", "31", r#"<div class="month_block"><a href="x?id=1">08:00 -10:00<br>Mathe</a></div>"#);
+        assert_eq!(located(&problem), (DiagnosticKind::UnparseableEvent, Some("April 2023"), Some("31"), Some("08:00 -10:00 Mathe")));
+        assert_eq!(problem.message, "Invalid date 31.4.2023");
+    }
+
+    #[test]
+    fn rejects_blocks_without_links() {
+        let problem = unparseable("März 2023", "2", r#"<div class="month_block">08:00 -10:00 Mathe</div>"#);
+        assert_eq!(located(&problem), (DiagnosticKind::UnparseableEvent, Some("März 2023"), Some("2"), Some("08:00 -10:00 Mathe")));
+        assert_eq!(problem.message, "No containing link in event!");
+    }
+
+    #[test]
+    fn rejects_malformed_time_ranges() {
+        let problem = unparseable("März 2023", "2", r#"<div class="month_block"><a href="x?id=1">8 Uhr bis 10 Uhr<br>Mathe</a></div>"#);
+        assert_eq!(located(&problem), (DiagnosticKind::UnparseableEvent, Some("März 2023"), Some("2"), Some("8 Uhr bis 10 Uhr Mathe")));
+        assert_eq!(problem.message, "Failed to parse event metadata!");
+
+        let problem = unparseable("März 2023", "2", r#"<div class="month_block"><a href="x?id=1">08:00 -25:00<br>Mathe</a></div>"#);
+        assert_eq!(located(&problem), (DiagnosticKind::UnparseableEvent, Some("März 2023"), Some("2"), Some("08:00 -25:00 Mathe")));
+        assert_eq!(problem.message, "Invalid time 25:00");
+    }
+
+    #[test]
+    fn records_calendars_without_headings() {
+        let (_dom, body) = parse_body(r#"<div class="calendar"><table><tbody><tr><td class="month_cell"><div>1</div></td></tr></tbody></table></div>"#);
+        let calendar = body.find_descendant(|handle| handle.is_tag("div")).unwrap();
+        let mut diagnostics = Diagnostics::new();
+        assert!(load_month(calendar, &Config::default(), &mut diagnostics).unwrap().is_none());
+        assert_eq!(diagnostics.problems.len(), 1);
+        assert_eq!(diagnostics.problems[0].kind, DiagnosticKind::SkippedCalendar);
+    }
+
+    #[test]
+    fn records_skipped_parts_of_cells() {
+        let (events, problems) = load("März 2023", SKIPPED_PARTS);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "Mathe");
+        assert_eq!(problems.len(), 2);
+        assert_eq!(located(&problems[0]), (DiagnosticKind::UnknownClass, Some("März 2023"), Some("7"), Some("Feiertag")));
+        assert_eq!(problems[0].message, r#"Skipping potential event with class "month_note""#);
+        assert_eq!(located(&problems[1]), (DiagnosticKind::SkippedCell, Some("März 2023"), Some("8."), None));
+        assert_eq!(problems[1].message, "Failed to parse day number: invalid digit found in string");
+    }
+
+    #[test]
+    fn reports_skipped_parts_of_cells() {
+        let (events, mut diagnostics) = load_with_diagnostics("März 2023", SKIPPED_PARTS);
+        let mut months = Months::new();
+        months.insert("202303".to_string(), events);
+        diagnostics.record_months(&months);
+
+        let path = test_directory("report").join("report.json");
+        diagnostics.write_report(path.to_str().unwrap()).unwrap();
+        let report: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report, json!({
+            "problems": [
+                {
+                    "kind": "unknown_class",
+                    "message": "Skipping potential event with class \"month_note\"",
+                    "input": null,
+                    "month": "März 2023",
+                    "day": "7",
+                    "event": "Feiertag",
+                },
+                {
+                    "kind": "skipped_cell",
+                    "message": "Failed to parse day number: invalid digit found in string",
+                    "input": null,
+                    "month": "März 2023",
+                    "day": "8.",
+                    "event": null,
+                },
+            ],
+            "months": { "202303": 1 },
+        }));
+    }
+}
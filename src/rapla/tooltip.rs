@@ -0,0 +1,217 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use markup5ever_rcdom::{Handle, NodeData};
+use regex::Regex;
+
+use crate::model::Lecturer;
+use crate::rapla::get_table_rows;
+use crate::util::{HandleExtensions, parse_german_datetime};
+
+/// The details of an event as found in the hidden tooltip of a Rapla calendar block.
+#[derive(Default)]
+pub struct Tooltip {
+    /// "Veranstaltungsname"
+    pub name: Option<String>,
+    /// "Veranstaltungsnummer"
+    pub number: Option<String>,
+    /// "Art"
+    pub kind: Option<String>,
+    /// "Sprache"
+    pub language: Option<String>,
+    /// "Kategorie"
+    pub categories: Vec<String>,
+    /// "Stunden"
+    pub total_hours: Option<u32>,
+    /// "Ressourcen"
+    pub resources: Vec<String>,
+    /// "Personen"
+    pub lecturers: Vec<Lecturer>,
+    /// "Ersteller"
+    pub creator: Option<String>,
+    /// "erstellt am"
+    pub created: Option<DateTime<Utc>>,
+    /// "zuletzt geändert"
+    pub last_changed: Option<DateTime<Utc>>,
+}
+
+/// Looks for a tooltip in the given calendar block and reads its details.
+/// Returns `None` if the block doesn't contain a tooltip.
+pub fn parse_tooltip(block_handle: &Handle) -> Option<Tooltip> {
+    let tooltip_handle = block_handle.find_descendant(|handle| {
+        handle.clone().check_attribute("class", "tooltip").is_ok()
+    })?;
+
+    let mut tooltip = Tooltip::default();
+
+    if let Some(table_handle) = tooltip_handle.find_descendant(|handle| handle.is_tag("table")) {
+        for row_handle in get_table_rows(&table_handle) {
+            let cells = row_handle.get_nodes_by_tag_name("td");
+            if cells.len() < 2 {
+                continue;
+            }
+
+            let label = cells[0].get_text();
+            let label = label.trim().trim_end_matches(':').trim_end();
+            let values = get_cell_values(&cells[1]);
+            tooltip.apply_row(label, values);
+        }
+    }
+
+    // Rapla usually puts the creation and modification dates in an italic line above the table
+    let text = tooltip_handle.get_text();
+    if tooltip.created.is_none() {
+        if let Some(index) = text.find("erstellt am") {
+            tooltip.created = parse_german_datetime(&text[index..]);
+        }
+    }
+    if tooltip.last_changed.is_none() {
+        if let Some(index) = text.find("zuletzt geändert") {
+            tooltip.last_changed = parse_german_datetime(&text[index..]);
+        }
+    }
+
+    Some(tooltip)
+}
+
+impl Tooltip {
+    /// Applies a labeled value as found in the tooltip table.
+    pub fn apply_row(&mut self, label: &str, values: Vec<String>) {
+        let joined = || Some(values.join(" ")).filter(|value| !value.is_empty());
+        let split = || values.iter()
+            .flat_map(|value| value.split(','))
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<String>>();
+
+        match label.to_lowercase().as_str() {
+            "veranstaltungsname" | "name" | "titel" => self.name = joined(),
+            "veranstaltungsnummer" | "nummer" => self.number = joined(),
+            "art" => self.kind = joined(),
+            "sprache" => self.language = joined(),
+            "kategorie" | "kategorien" => self.categories = split(),
+            "stunden" => self.total_hours = joined().and_then(|hours| parse_leading_number(&hours)),
+            "ressourcen" => self.resources = split(),
+            // Names are usually written as "Last, First", so commas can't be used to separate persons
+            "personen" | "dozent" | "dozenten" | "dozent:innen" => self.lecturers = values.into_iter()
+                .map(|name| Lecturer { name })
+                .collect(),
+            "ersteller" => self.creator = joined(),
+            "erstellt" | "erstellt am" => self.created = joined().and_then(|text| parse_german_datetime(&text)),
+            "zuletzt geändert" | "zuletzt geändert am" => self.last_changed = joined().and_then(|text| parse_german_datetime(&text)),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the non-empty lines of a table cell, as separated by `<br>` tags.
+pub fn get_cell_values(cell_handle: &Handle) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut current = String::new();
+    for child in cell_handle.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { contents } => current.push_str(&contents.borrow()),
+            NodeData::Element { name, .. } if &name.local == "br" => {
+                values.push(std::mem::take(&mut current));
+            }
+            NodeData::Element { .. } => current.push_str(&child.get_text()),
+            _ => {}
+        }
+    }
+    values.push(current);
+
+    values.into_iter()
+        .map(|value| value.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn parse_leading_number(text: &str) -> Option<u32> {
+    lazy_static! {
+        static ref NUMBER_PATTERN: Regex = Regex::new(r"^\s*(\d+)").unwrap();
+    }
+    NUMBER_PATTERN.captures(text)?.get(1)?.as_str().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::rapla::parse_body;
+
+    use super::*;
+
+    const BLOCK: &str = r#"<div class="month_block"><a href="/rapla?id=1">08:00 -12:30 TIN-21B3,A 4.12<br>Mathematik<span class="tooltip">
+<strong>Lehrveranstaltung</strong><br><i>erstellt am 05.01.23 10:00 zuletzt geändert am Fr. 10.02.23 12:34</i><table>
+<tr><td class="label">Veranstaltungsname:</td><td class="value">Mathematik</td></tr>
+<tr><td class="label">Art:</td><td class="value">Vorlesung</td></tr>
+<tr><td class="label">Stunden:</td><td class="value">48 Std.</td></tr>
+<tr><td class="label">Kategorie:</td><td class="value">Pflicht, Grundlagen</td></tr>
+<tr><td class="label">Ressourcen:</td><td class="value">TIN-21B3<br>A 4.12</td></tr>
+<tr><td class="label">Personen:</td><td class="value">Mustermann, Max<br>Musterfrau, Erika</td></tr>
+<tr><td class="label">Ersteller:</td><td class="value">Sekretariat</td></tr>
+</table></span></a></div>"#;
+
+    fn read_tooltip(html: &str) -> Option<Tooltip> {
+        let (_dom, body) = parse_body(html);
+        parse_tooltip(&body.find_descendant(|handle| handle.is_tag("div")).unwrap())
+    }
+
+    fn cell_values(html: &str) -> Vec<String> {
+        let (_dom, body) = parse_body(&format!("<table><tr><td>{}</td></tr></table>", html));
+        get_cell_values(&body.find_descendant(|handle| handle.is_tag("td")).unwrap())
+    }
+
+    #[test]
+    fn parses_tooltip_table() {
+        let tooltip = read_tooltip(BLOCK).unwrap();
+
+        assert_eq!(tooltip.name.as_deref(), Some("Mathematik"));
+        assert_eq!(tooltip.kind.as_deref(), Some("Vorlesung"));
+        assert_eq!(tooltip.total_hours, Some(48));
+        assert_eq!(tooltip.categories, vec!["Pflicht", "Grundlagen"]);
+        assert_eq!(tooltip.resources, vec!["TIN-21B3", "A 4.12"]);
+        let lecturers: Vec<&str> = tooltip.lecturers.iter().map(|lecturer| lecturer.name.as_str()).collect();
+        assert_eq!(lecturers, vec!["Mustermann, Max", "Musterfrau, Erika"]);
+        assert_eq!(tooltip.creator.as_deref(), Some("Sekretariat"));
+    }
+
+    #[test]
+    fn keeps_creation_and_last_change_apart() {
+        let tooltip = read_tooltip(BLOCK).unwrap();
+        assert_eq!(tooltip.created, Some(Utc.ymd(2023, 1, 5).and_hms(9, 0, 0)));
+        assert_eq!(tooltip.last_changed, Some(Utc.ymd(2023, 2, 10).and_hms(11, 34, 0)));
+
+        let changed_only = BLOCK.replace("erstellt am 05.01.23 10:00 ", "");
+        let tooltip = read_tooltip(&changed_only).unwrap();
+        assert_eq!(tooltip.created, None);
+        assert_eq!(tooltip.last_changed, Some(Utc.ymd(2023, 2, 10).and_hms(11, 34, 0)));
+    }
+
+    #[test]
+    fn ignores_blocks_without_tooltip() {
+        let html = r#"<div class="month_block"><a href="/rapla?id=1">08:00 -12:30 TIN-21B3<br>Mathematik</a></div>"#;
+        assert!(read_tooltip(html).is_none());
+    }
+
+    #[test]
+    fn applies_rows_by_label() {
+        let mut tooltip = Tooltip::default();
+        tooltip.apply_row("VERANSTALTUNGSNUMMER", vec!["T3INF1001".to_string()]);
+        tooltip.apply_row("zuletzt geändert am", vec!["01.02.23 10:00".to_string()]);
+        tooltip.apply_row("Erstellt am", vec!["15.01.2023".to_string()]);
+        tooltip.apply_row("Stunden", vec!["keine".to_string()]);
+        tooltip.apply_row("Raum", vec!["A 4.12".to_string()]);
+
+        assert_eq!(tooltip.number.as_deref(), Some("T3INF1001"));
+        assert_eq!(tooltip.last_changed, Some(Utc.ymd(2023, 2, 1).and_hms(9, 0, 0)));
+        assert_eq!(tooltip.created, Some(Utc.ymd(2023, 1, 14).and_hms(23, 0, 0)));
+        assert_eq!(tooltip.total_hours, None);
+        assert!(tooltip.resources.is_empty());
+    }
+
+    #[test]
+    fn splits_cell_values_at_line_breaks() {
+        assert_eq!(cell_values("Mustermann,\n  Max<br>Musterfrau, <b>Erika</b><br> <br>"), vec!["Mustermann, Max", "Musterfrau, Erika"]);
+        assert!(cell_values("  ").is_empty());
+    }
+}
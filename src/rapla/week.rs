@@ -0,0 +1,219 @@
+use chrono::{Datelike, NaiveDate};
+use lazy_static::lazy_static;
+use markup5ever_rcdom::Handle;
+use regex::Regex;
+
+use crate::config::Config;
+use crate::diagnostics::{DiagnosticKind, Diagnostics};
+use crate::model::{DateRange, Event};
+use crate::rapla::event::process_event;
+use crate::rapla::get_table_rows;
+use crate::util::{Error, HandleExtensions, Year};
+
+/// Loads all events from a Rapla week table, together with the range of dates in its weekday headers.
+///
+/// The week table is a grid with one row per time slot and a group of columns per weekday.
+/// Events are cells spanning multiple rows, so the weekday of an event has to be derived
+/// by laying out the table the way a browser would.
+pub fn load_week(
+    table_handle: Handle, year: Year, config: &Config, diagnostics: &mut Diagnostics,
+) -> Result<(Vec<Event>, Option<DateRange>), Error> {
+    let mut column_dates: Vec<Option<NaiveDate>> = Vec::new();
+    // The number of rows that each column is still occupied for by cells of previous rows
+    let mut occupied: Vec<usize> = Vec::new();
+    let mut last_date: Option<NaiveDate> = None;
+    let mut events = Vec::new();
+
+    for row_handle in get_table_rows(&table_handle) {
+        let mut column = 0;
+        let cells: Vec<Handle> = row_handle.children.borrow().iter()
+            .filter(|handle| handle.is_tag("td") || handle.is_tag("th"))
+            .cloned()
+            .collect();
+        for cell_handle in cells {
+            while occupied.get(column).is_some_and(|rows| *rows > 0) {
+                column += 1;
+            }
+
+            let column_span = get_span(&cell_handle, "colspan");
+            let row_span = get_span(&cell_handle, "rowspan");
+            let columns = column..column + column_span;
+            if occupied.len() < columns.end {
+                occupied.resize(columns.end, 0);
+                column_dates.resize(columns.end, None);
+            }
+
+            match cell_handle.get_attribute_value("class").as_deref() {
+                Some("week_header") => {
+                    let header_text = cell_handle.get_text();
+                    let date = parse_header_date(&header_text, year, last_date)
+                        .map_err(|error| error.on_day(&header_text))?;
+                    last_date = Some(date);
+                    for date_column in columns.clone() {
+                        column_dates[date_column] = Some(date);
+                    }
+                }
+                Some("week_block") => {
+                    let date = column_dates[column].ok_or_else(|| {
+                        Error::from("Found event in week table without a weekday header!").in_event(&cell_handle.get_text())
+                    })?;
+                    match process_event(cell_handle.clone(), date.year(), date.month(), date.day(), config) {
+                        Ok(event) => events.push(event),
+                        Err(error) => diagnostics.record(
+                            DiagnosticKind::UnparseableEvent,
+                            error.on_day(&date.format("%d.%m.%Y").to_string()),
+                        ),
+                    }
+                }
+                _ => {}
+            }
+
+            for occupied_column in columns {
+                occupied[occupied_column] = row_span;
+            }
+            column += column_span;
+        }
+
+        for rows in occupied.iter_mut() {
+            *rows = rows.saturating_sub(1);
+        }
+    }
+
+    let covered = DateRange::spanning(column_dates.into_iter().flatten());
+    Ok((events, covered))
+}
+
+/// Tries to find the year of a week view, which is not part of the weekday headers.
+pub fn find_week_year(root_handle: &Handle) -> Option<Year> {
+    lazy_static! {
+        static ref YEAR_PATTERN: Regex = Regex::new(r"\b(\d{4})\b").unwrap();
+    }
+
+    // Rapla's navigation form contains the selected date
+    let year_input = root_handle.find_descendant(|handle| {
+        handle.get_attribute_value("name").as_deref() == Some("year")
+            && handle.get_attribute_value("value").is_some()
+    });
+    if let Some(year) = year_input.and_then(|input| input.get_attribute_value("value")?.trim().parse().ok()) {
+        return Some(year);
+    }
+
+    let heading = root_handle.find_descendant(|handle| {
+        handle.is_tag("h2") && YEAR_PATTERN.is_match(&handle.get_text())
+    })?;
+    YEAR_PATTERN.captures(&heading.get_text())?.get(1)?.as_str().parse().ok()
+}
+
+fn get_span(cell_handle: &Handle, attribute_name: &str) -> usize {
+    cell_handle.get_attribute_value(attribute_name)
+        .and_then(|span| span.trim().parse().ok())
+        .filter(|span| *span > 0)
+        .unwrap_or(1)
+}
+
+/// Parses headers like `Mo 06.03.` or `Mo 06.03.2023`.
+/// If the header doesn't contain a year, a wrap around new year's eve is detected from the previous header.
+/// The first header may also belong to the year before or after the given one, which is told by its weekday.
+fn parse_header_date(text: &str, year: Year, last_date: Option<NaiveDate>) -> Result<NaiveDate, Error> {
+    lazy_static! {
+        static ref HEADER_PATTERN: Regex = Regex::new(r"(\d{1,2})\.(\d{1,2})\.(\d{4})?").unwrap();
+        static ref WEEKDAY_PATTERN: Regex = Regex::new(r"^\s*(Mo|Di|Mi|Do|Fr|Sa|So)").unwrap();
+    }
+
+    let captures = HEADER_PATTERN.captures(text)
+        .ok_or_else(|| format!("Invalid weekday header \"{}\"", text.trim()))?;
+    let day = captures[1].parse().map_err(|_| format!("Invalid day in weekday header \"{}\"", text.trim()))?;
+    let month = captures[2].parse().map_err(|_| format!("Invalid month in weekday header \"{}\"", text.trim()))?;
+
+    let year = match captures.get(3) {
+        Some(year) => year.as_str().parse().map_err(|_| format!("Invalid year in weekday header \"{}\"", text.trim()))?,
+        None => match last_date {
+            Some(last_date) if month < last_date.month() => last_date.year() + 1,
+            Some(last_date) => last_date.year(),
+            None => {
+                let weekday = WEEKDAY_PATTERN.captures(text).map(|captures| captures[1].to_string());
+                [year, year - 1, year + 1].iter().copied()
+                    .find(|&year| {
+                        let date = NaiveDate::from_ymd_opt(year, month, day);
+                        weekday.as_deref().is_some_and(|weekday| date.is_some_and(|date| german_weekday(date) == weekday))
+                    })
+                    .unwrap_or(year)
+            }
+        },
+    };
+
+    NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| format!("Invalid date in weekday header \"{}\"", text.trim()).into())
+}
+
+fn german_weekday(date: NaiveDate) -> &'static str {
+    ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"][date.weekday().num_days_from_monday() as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rapla::parse_body;
+
+    use super::*;
+
+    const WEEK: &str = r#"<table class="week_table"><tbody>
+<tr><td class="week_number">KW 9</td><td class="week_header" colspan="2"><nobr>Mo 27.02.</nobr></td><td class="week_header"><nobr>Di 28.02.</nobr></td><td class="week_header"><nobr>Mi 01.03.</nobr></td></tr>
+<tr><th class="week_times" rowspan="4">08:00</th><td class="week_block" rowspan="4"><a href="x?id=1">08:00&#160;-10:00<br>Mathe</a></td><td class="week_block" rowspan="2"><a href="x?id=2">08:00&#160;-09:00<br>Englisch</a></td><td class="week_emptycell"></td><td class="week_emptycell"></td></tr>
+<tr><td class="week_block" rowspan="3"><a href="x?id=3">09:00&#160;-10:00<br>Physik</a></td><td class="week_emptycell"></td></tr>
+<tr><td class="week_emptycell"></td><td class="week_block"><a href="x?id=4">09:30&#160;-10:00<br>Chemie</a></td></tr>
+</tbody></table>"#;
+
+    const NEW_YEAR: &str = r#"<table class="week_table"><tbody>
+<tr><td class="week_number">KW 52</td><td class="week_header">Do 29.12.</td><td class="week_header">Fr 30.12.</td><td class="week_header">Mo 02.01.</td></tr>
+<tr><th class="week_times">08:00</th><td class="week_block"><a href="x?id=1">08:00&#160;-10:00<br>Mathe</a></td><td class="week_emptycell"></td><td class="week_block"><a href="x?id=2">08:00&#160;-10:00<br>Physik</a></td></tr>
+</tbody></table>"#;
+
+    fn load(html: &str, year: Year) -> Vec<(String, NaiveDate)> {
+        let (_dom, body) = parse_body(html);
+        let table = body.find_descendant(|handle| handle.is_tag("table")).unwrap();
+        let mut diagnostics = Diagnostics::new();
+        let (events, _) = load_week(table, year, &Config::default(), &mut diagnostics).unwrap();
+        assert!(diagnostics.is_empty());
+        events.into_iter().map(|event| (event.name, event.begin.naive_utc().date())).collect()
+    }
+
+    fn date(year: Year, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn lays_out_blocks_spanning_rows_and_columns() {
+        assert_eq!(load(WEEK, 2023), vec![
+            ("Mathe".to_string(), date(2023, 2, 27)),
+            ("Englisch".to_string(), date(2023, 2, 27)),
+            ("Physik".to_string(), date(2023, 2, 28)),
+            ("Chemie".to_string(), date(2023, 3, 1)),
+        ]);
+    }
+
+    #[test]
+    fn wraps_weeks_around_new_year() {
+        let expected = vec![("Mathe".to_string(), date(2022, 12, 29)), ("Physik".to_string(), date(2023, 1, 2))];
+        assert_eq!(load(NEW_YEAR, 2022), expected);
+        // The year of the navigation form may already be the one of the selected January day
+        assert_eq!(load(NEW_YEAR, 2023), expected);
+    }
+
+    #[test]
+    fn covers_the_dates_of_its_headers() {
+        let (_dom, body) = parse_body(NEW_YEAR);
+        let table = body.find_descendant(|handle| handle.is_tag("table")).unwrap();
+        let (_, covered) = load_week(table, 2022, &Config::default(), &mut Diagnostics::new()).unwrap();
+        assert_eq!(covered, Some(DateRange { first: date(2022, 12, 29), last: date(2023, 1, 2) }));
+    }
+
+    #[test]
+    fn parses_header_dates() {
+        assert_eq!(parse_header_date("Mo 06.03.", 2023, None).unwrap(), date(2023, 3, 6));
+        assert_eq!(parse_header_date("Mo 02.01.2023", 2022, Some(date(2022, 12, 30))).unwrap(), date(2023, 1, 2));
+        assert_eq!(parse_header_date("Mo 02.01.", 2022, Some(date(2022, 12, 30))).unwrap(), date(2023, 1, 2));
+        assert_eq!(parse_header_date("06.03.", 2023, None).unwrap(), date(2023, 3, 6));
+        assert!(parse_header_date("Mo 31.02.", 2023, None).is_err());
+        assert!(parse_header_date("Montag", 2023, None).is_err());
+    }
+}
@@ -1,41 +1,68 @@
-use std::fmt::Debug;
+use std::ffi::OsString;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "html")]
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
-use markup5ever_rcdom::Handle;
-use markup5ever_rcdom::NodeData;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use fs2::FileExt;
+use lazy_static::lazy_static;
+#[cfg(feature = "html")]
+use markup5ever_rcdom::{Handle, NodeData};
+use regex::Regex;
+use serde::Serialize;
 
-use crate::util::Error::Custom;
+use crate::util::Error::{Archive, Config, Input, Output, Parse};
 
+/// Helpers for navigating the DOM of a Rapla page
+#[cfg(feature = "html")]
 pub trait HandleExtensions {
     fn check_attribute(self, attribute_name: &str, attribute_value: &str) -> Result<Handle, Error>;
 
+    fn is_tag(&self, tag_name: &str) -> bool;
+
     fn get_node_by_tag_name(&self, tag_name: &str) -> Option<Handle>;
     fn get_nodes_by_tag_name(&self, tag_name: &str) -> Vec<Handle>;
 
     fn get_attribute_value(&self, attribute_name: &str) -> Option<String>;
 
+    fn find_descendant<P: Fn(&Handle) -> bool>(&self, predicate: P) -> Option<Handle>;
+
     fn get_content(&self) -> Option<String>;
     fn get_text_nodes(&self) -> Vec<String>;
+    /// Collects the text of this node and all of its descendants
+    fn get_text(&self) -> String;
 }
 
+#[cfg(feature = "html")]
 impl HandleExtensions for Handle {
     fn check_attribute(self, attribute_name: &str, attribute_value: &str) -> Result<Self, Error> {
         let val = self.get_attribute_value(attribute_name).ok_or(format!("No such attribute: {}", attribute_name))?;
-        if &val == attribute_value {
+        if val == attribute_value {
             Ok(self)
         } else {
             Err(format!("Unexpected value {} for attribute {}, expected {}", val, attribute_name, attribute_value).into())
         }
     }
 
+    fn is_tag(&self, tag_name: &str) -> bool {
+        if let NodeData::Element { name, .. } = &self.data {
+            return tag_name == &name.local;
+        }
+        false
+    }
+
     fn get_node_by_tag_name(&self, tag_name: &str) -> Option<Handle> {
         let children = self.children.borrow();
-        children.iter().filter(|handle| {
+        children.iter().find(|handle| {
             if let NodeData::Element { name, .. } = &handle.data {
                 return tag_name == &name.local;
             }
             false
-        }).next().map(|handle| handle.clone())
+        }).cloned()
     }
 
     fn get_nodes_by_tag_name(&self, tag_name: &str) -> Vec<Handle> {
@@ -45,7 +72,7 @@ impl HandleExtensions for Handle {
                 return tag_name == &name.local;
             }
             false
-        }).map(|val| val.clone()).collect()
+        }).cloned().collect()
     }
 
     fn get_attribute_value(&self, attribute_name: &str) -> Option<String> {
@@ -59,6 +86,21 @@ impl HandleExtensions for Handle {
         None
     }
 
+    fn find_descendant<P: Fn(&Handle) -> bool>(&self, predicate: P) -> Option<Handle> {
+        fn find(handle: &Handle, predicate: &dyn Fn(&Handle) -> bool) -> Option<Handle> {
+            for child in handle.children.borrow().iter() {
+                if predicate(child) {
+                    return Some(child.clone());
+                }
+                if let Some(found) = find(child, predicate) {
+                    return Some(found);
+                }
+            }
+            None
+        }
+        find(self, &predicate)
+    }
+
     fn get_content(&self) -> Option<String> {
         for child in &*self.children.borrow() {
             if let NodeData::Text { contents } = &child.data {
@@ -75,6 +117,18 @@ impl HandleExtensions for Handle {
                 _ => None,
             }).collect()
     }
+
+    fn get_text(&self) -> String {
+        let mut text = String::new();
+        for child in self.children.borrow().iter() {
+            match &child.data {
+                NodeData::Text { contents } => text.push_str(&contents.borrow()),
+                NodeData::Element { .. } => text.push_str(&child.get_text()),
+                _ => {}
+            }
+        }
+        text
+    }
 }
 
 pub type Year = i32;
@@ -101,14 +155,198 @@ pub fn get_month_from_german(text: &str) -> Result<Month, Error> {
     }
 }
 
+/// Parses dates like `Mo. 12.09.22 13:37` or `12.09.2022` in Berlin time.
+pub fn parse_german_datetime(text: &str) -> Option<DateTime<Utc>> {
+    lazy_static! {
+        static ref DATETIME_PATTERN: Regex = Regex::new(r"(\d{1,2})\.(\d{1,2})\.(\d{4}|\d{2})(?:\s+(\d{1,2}):(\d{2}))?").unwrap();
+    }
+    let captures = DATETIME_PATTERN.captures(text)?;
+    let number = |index: usize| captures.get(index).and_then(|value| value.as_str().parse::<u32>().ok());
+
+    let mut year = number(3)? as i32;
+    if year < 100 {
+        year += 2000;
+    }
+    let date = NaiveDate::from_ymd_opt(year, number(2)?, number(1)?)?;
+    let date_time = date.and_hms_opt(number(4).unwrap_or(0), number(5).unwrap_or(0), 0)?;
+    Berlin.from_local_datetime(&date_time).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+/// Appends a suffix to the file name of a path, like `.bak` to `archive.json`.
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Writes a file by writing a temporary sibling first and renaming it over the file,
+/// so that the file is never left half-written.
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let temp_path = sibling_path(path, &format!(".{}.tmp", std::process::id()));
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// An advisory lock on a file, released when dropped
+pub struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    /// Locks the file, creating it if necessary and waiting for other processes holding the lock.
+    /// `on_wait` is called before waiting, so that the caller can tell why nothing happens.
+    pub fn acquire<F: FnOnce()>(path: &Path, on_wait: F) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(path)?;
+        if file.try_lock_exclusive().is_err() {
+            on_wait();
+            file.lock_exclusive()?;
+        }
+        Ok(LockFile { file })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Creates an empty directory for a test that works with files, named after the test and unique to the process.
+#[cfg(test)]
+pub(crate) fn test_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("icalnigma-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// The date of the given time in Berlin.
+pub fn local_date(time: DateTime<Utc>) -> NaiveDate {
+    time.with_timezone(&Berlin).naive_local().date()
+}
+
+/// The errors that can fail a run, grouped by the stage they occur in
 #[derive(Debug)]
 pub enum Error {
-    Custom(String)
+    /// The input could not be read
+    Input(String),
+    /// The input could not be understood
+    Parse(String, Location),
+    /// The archive could not be read or written
+    Archive(String),
+    /// The calendar could not be written
+    Output(String),
+    /// The configuration file could not be loaded
+    Config(String),
+}
+
+/// Where in the input a parse error occurred
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Location {
+    /// The input file
+    pub input: Option<String>,
+    /// The heading of the month or week
+    pub month: Option<String>,
+    /// The text identifying the day, like the day number of a month cell
+    pub day: Option<String>,
+    /// The raw text of the event
+    pub event: Option<String>,
 }
 
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(input) = &self.input {
+            parts.push(format!("input {:?}", input));
+        }
+        if let Some(month) = &self.month {
+            parts.push(format!("month {:?}", month));
+        }
+        if let Some(day) = &self.day {
+            parts.push(format!("day {:?}", day));
+        }
+        if let Some(event) = &self.event {
+            parts.push(format!("event {:?}", event));
+        }
+
+        if !parts.is_empty() {
+            write!(f, " ({})", parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// Records the input file that a parse error occurred in, unless it is already known.
+    pub fn in_input(self, path: &str) -> Self {
+        self.locate(|location| location.input.get_or_insert_with(|| path.to_string()))
+    }
+
+    /// Records the month that a parse error occurred in, unless it is already known.
+    pub fn in_month(self, heading: &str) -> Self {
+        self.locate(|location| location.month.get_or_insert_with(|| heading.trim().to_string()))
+    }
+
+    /// Records the day that a parse error occurred on, unless it is already known.
+    pub fn on_day(self, day: &str) -> Self {
+        self.locate(|location| location.day.get_or_insert_with(|| day.trim().to_string()))
+    }
+
+    /// Records the event that a parse error occurred in, unless it is already known.
+    pub fn in_event(self, text: &str) -> Self {
+        self.locate(|location| location.event.get_or_insert_with(|| text.split_whitespace().collect::<Vec<&str>>().join(" ")))
+    }
+
+    fn locate<F: FnOnce(&mut Location) -> &mut String>(self, update: F) -> Self {
+        match self {
+            Parse(text, mut location) => {
+                update(&mut location);
+                Parse(text, location)
+            }
+            error => error,
+        }
+    }
+
+    /// The exit code that the program should end with for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Input(_) => 3,
+            Parse(..) => 4,
+            Archive(_) => 5,
+            Output(_) => 6,
+            Config(_) => 7,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Input(text) => write!(f, "Failed to read input: {}", text),
+            Parse(text, location) => write!(f, "Failed to parse input: {}{}", text, location),
+            Archive(text) => write!(f, "Archive error: {}", text),
+            Output(text) => write!(f, "Failed to write output: {}", text),
+            Config(text) => write!(f, "Invalid configuration: {}", text),
+        }
+    }
+}
+
+/// Plain messages are used throughout the parser, so they are treated as parse errors
 impl From<String> for Error {
     fn from(text: String) -> Self {
-        Custom(text)
+        Parse(text, Location::default())
     }
 }
 
@@ -117,3 +355,54 @@ impl From<&str> for Error {
         text.to_string().into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn parses_german_datetimes() {
+        assert_eq!(parse_german_datetime("Mo. 12.09.22 13:37"), Some(Utc.ymd(2022, 9, 12).and_hms(11, 37, 0)));
+        assert_eq!(parse_german_datetime("12.09.2022"), Some(Utc.ymd(2022, 9, 11).and_hms(22, 0, 0)));
+        assert_eq!(parse_german_datetime("zuletzt geändert am 1.2.23 9:05"), Some(Utc.ymd(2023, 2, 1).and_hms(8, 5, 0)));
+        assert_eq!(parse_german_datetime("31.02.23"), None);
+        assert_eq!(parse_german_datetime("bald"), None);
+    }
+
+    #[test]
+    fn keeps_files_if_writing_fails() {
+        let directory = test_directory("atomic");
+        let path = directory.join("calendar.ics");
+        fs::write(&path, "original").unwrap();
+
+        let result = write_atomically(&path, |writer| {
+            writer.write_all(b"partial")?;
+            Err(io::Error::other("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert!(!sibling_path(&path, &format!(".{}.tmp", std::process::id())).exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn waits_for_held_locks() {
+        let directory = test_directory("lock");
+        let path = directory.join("archive.json.lock");
+        let held = LockFile::acquire(&path, || panic!("The free lock was waited for")).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let waiting_path = path.clone();
+        let waiting = thread::spawn(move || {
+            LockFile::acquire(&waiting_path, || sender.send(()).unwrap()).unwrap();
+        });
+        receiver.recv_timeout(Duration::from_secs(5)).expect("The held lock wasn't waited for");
+        drop(held);
+        waiting.join().unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+}